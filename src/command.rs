@@ -1,3 +1,4 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter};
@@ -7,6 +8,33 @@ pub enum Command {
     Upload,
     Download,
     List,
+    History,
+    Rollback,
+    Status,
+    Delete,
+    Init,
+}
+
+// Version to make current with the rollback command
+#[derive(Debug, Default, PartialEq)]
+pub enum RollbackTarget {
+    // The version preceding the current one
+    #[default]
+    Previous,
+    Version(i32),
+    // The last version uploaded at or before this UTC time, compared with the upload date
+    Date(NaiveDateTime),
+}
+
+// Order of files shown by the list command
+#[derive(Debug, Default, PartialEq, Clone, Copy, Display, EnumIter)]
+pub enum SortOrder {
+    #[default]
+    Name,
+    // newest first
+    Date,
+    // largest first
+    Size,
 }
 
 impl Command {
@@ -28,6 +56,44 @@ impl FromStr for Command {
     }
 }
 
+impl FromStr for SortOrder {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<SortOrder, Self::Err> {
+        SortOrder::iter()
+            .find(|order| input.eq_ignore_ascii_case(&order.to_string()))
+            .ok_or(())
+    }
+}
+
+impl FromStr for RollbackTarget {
+    type Err = ();
+
+    // Accepts a version number or a local date/time as shown in the upload date column
+    // of the history command
+    fn from_str(input: &str) -> Result<RollbackTarget, Self::Err> {
+        if let Ok(version) = input.parse() {
+            return Ok(RollbackTarget::Version(version));
+        }
+
+        let local_date = NaiveDateTime::parse_from_str(input, "%d/%m/%Y %T")
+            .or_else(|_| NaiveDateTime::parse_from_str(input, "%Y-%m-%d %T"))
+            .or_else(|_| {
+                NaiveDate::parse_from_str(input, "%d/%m/%Y")
+                    .or_else(|_| NaiveDate::parse_from_str(input, "%Y-%m-%d"))
+                    // whole day is included
+                    .map(|date| date.and_hms_opt(23, 59, 59).unwrap_or_default())
+            })
+            .map_err(|_| ())?;
+
+        Local
+            .from_local_datetime(&local_date)
+            .earliest()
+            .map(|date| RollbackTarget::Date(date.with_timezone(&Utc).naive_utc()))
+            .ok_or(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,6 +124,47 @@ mod tests {
             Command::from_str_case_insensitive("list"),
             Ok(Command::List)
         );
+        assert_eq!(
+            Command::from_str_case_insensitive("History"),
+            Ok(Command::History)
+        );
+        assert_eq!(
+            Command::from_str_case_insensitive("history"),
+            Ok(Command::History)
+        );
+        assert_eq!(
+            Command::from_str_case_insensitive("rollback"),
+            Ok(Command::Rollback)
+        );
+        assert_eq!(
+            Command::from_str_case_insensitive("Status"),
+            Ok(Command::Status)
+        );
+        assert_eq!(
+            Command::from_str_case_insensitive("delete"),
+            Ok(Command::Delete)
+        );
+        assert_eq!(
+            Command::from_str_case_insensitive("init"),
+            Ok(Command::Init)
+        );
         assert_eq!(Command::from_str_case_insensitive("invalid"), Err(()));
     }
+
+    #[test]
+    fn test_rollback_target_from_str() {
+        assert_eq!(
+            RollbackTarget::from_str("3"),
+            Ok(RollbackTarget::Version(3))
+        );
+        assert!(matches!(
+            RollbackTarget::from_str("01/02/2026 10:00:00"),
+            Ok(RollbackTarget::Date(_))
+        ));
+        assert!(matches!(
+            RollbackTarget::from_str("2026-02-01"),
+            Ok(RollbackTarget::Date(_))
+        ));
+        assert_eq!(RollbackTarget::from_str("yesterday"), Err(()));
+    }
 }
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use flate2::{
+    Compression as GzipLevel,
+    write::{GzDecoder, GzEncoder},
+};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+use crate::error::UpdateError;
+
+// Compression of file images, configured per update mode. The method is stored in the
+// Compression column next to the image, rows without it are not compressed.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    // Value of the Compression column, null for uncompressed images
+    pub fn marker(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Zstd => Some("zstd"),
+            Compression::Gzip => Some("gzip"),
+        }
+    }
+
+    pub fn from_marker(marker: Option<&str>) -> Result<Compression> {
+        match marker {
+            None => Ok(Compression::None),
+            Some("zstd") => Ok(Compression::Zstd),
+            Some("gzip") => Ok(Compression::Gzip),
+            Some(marker) => Err(UpdateError::Database(format!(
+                "Unknown compression '{marker}' of stored file image"
+            ))
+            .into()),
+        }
+    }
+
+    // Compresses everything written to it into the writer
+    pub fn encoder<W: Write>(self, writer: W) -> Result<Encoder<W>> {
+        Ok(match self {
+            Compression::None => Encoder::None(writer),
+            Compression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(
+                writer,
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(writer, GzipLevel::default())),
+        })
+    }
+
+    // Decompresses everything written to it into the writer
+    pub fn decoder<W: Write>(self, writer: W) -> Result<Decoder<W>> {
+        Ok(match self {
+            Compression::None => Decoder::None(writer),
+            Compression::Zstd => Decoder::Zstd(zstd::stream::write::Decoder::new(writer)?),
+            Compression::Gzip => Decoder::Gzip(GzDecoder::new(writer)),
+        })
+    }
+}
+
+pub enum Encoder<W: Write> {
+    None(W),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    // Compressed data written so far, it can be taken out of a Vec while compressing
+    pub fn get_mut(&mut self) -> &mut W {
+        match self {
+            Encoder::None(writer) => writer,
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+        }
+    }
+
+    // Writes the end of the compressed stream and returns the writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+pub enum Decoder<W: Write> {
+    None(W),
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+    Gzip(GzDecoder<W>),
+}
+
+impl<W: Write> Decoder<W> {
+    // Writes out the remaining decompressed data and returns the writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Decoder::None(writer) => Ok(writer),
+            Decoder::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+            Decoder::Gzip(decoder) => decoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Decoder::None(writer) => writer.write(buf),
+            Decoder::Zstd(decoder) => decoder.write(buf),
+            Decoder::Gzip(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Decoder::None(writer) => writer.flush(),
+            Decoder::Zstd(decoder) => decoder.flush(),
+            Decoder::Gzip(decoder) => decoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(compression: Compression, content: &[u8]) -> Vec<u8> {
+        let mut encoder = compression.encoder(vec![]).unwrap();
+        // written in pieces and taken out while compressing, as uploads do
+        let mut image = vec![];
+        for piece in content.chunks(100) {
+            encoder.write_all(piece).unwrap();
+            image.append(encoder.get_mut());
+        }
+        image.append(&mut encoder.finish().unwrap());
+        image
+    }
+
+    fn decompress(compression: Compression, image: &[u8]) -> Vec<u8> {
+        let mut decoder = compression.decoder(vec![]).unwrap();
+        for piece in image.chunks(7) {
+            decoder.write_all(piece).unwrap();
+        }
+        decoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compress_and_decompress_in_pieces() {
+        let content = b"PolyMix PolyMix PolyMix PolyMix PolyMix PolyMix".repeat(100);
+
+        for compression in [Compression::None, Compression::Zstd, Compression::Gzip] {
+            let image = compress(compression, &content);
+            if compression != Compression::None {
+                assert!(image.len() < content.len());
+            }
+            assert_eq!(decompress(compression, &image), content);
+        }
+    }
+
+    #[test]
+    fn marker_round_trip() {
+        for compression in [Compression::None, Compression::Zstd, Compression::Gzip] {
+            assert_eq!(
+                Compression::from_marker(compression.marker()).unwrap(),
+                compression
+            );
+        }
+        assert!(Compression::from_marker(Some("lz4")).is_err());
+    }
+
+    #[test]
+    fn deserializes_lowercase_names() {
+        let compression: Compression = serde_json::from_str("\"zstd\"").unwrap();
+        assert_eq!(compression, Compression::Zstd);
+    }
+}
@@ -1,8 +1,16 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::compression::Compression;
+use crate::error::UpdateError;
+
+pub const CONFIG_ENV_VAR: &str = "POLYMIX_UPDATE_CONFIG";
+const CONFIG_FILE_NAME: &str = "settings.json";
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdateMode {
@@ -10,18 +18,258 @@ pub struct UpdateMode {
     pub file_mask: String,
     #[serde(default)]
     pub is_default: bool,
+    // directory files are uploaded from and downloaded to, relative to the current directory
+    #[serde(default)]
+    pub local_dir: Option<String>,
+    // file names are compared ignoring case unless set, needs a case sensitive
+    // collation of FileName columns in database
+    #[serde(default)]
+    pub case_sensitive: bool,
+    // compression of uploaded file images, "zstd" or "gzip"
+    #[serde(default)]
+    pub compression: Compression,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct Config {
+pub struct Profile {
     pub connection_string: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Config {
+    // used when no profiles are configured
+    #[serde(default)]
+    pub connection_string: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub default_profile: Option<String>,
     pub update_mode: Vec<UpdateMode>,
 }
 
+impl Config {
+    pub fn find_mode(&self, name: &str) -> Option<&UpdateMode> {
+        self.update_mode
+            .iter()
+            .find(|mode| mode.name.eq_ignore_ascii_case(name))
+    }
+
+    // Validation guarantees there is exactly one default mode
+    pub fn default_mode(&self) -> Option<&UpdateMode> {
+        self.update_mode.iter().find(|mode| mode.is_default)
+    }
+
+    // Profile given with --profile, then the default profile, then top level connection string
+    pub fn connection_string(&self, profile: Option<&str>) -> Result<&str> {
+        match profile.or(self.default_profile.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(|profile| profile.connection_string.as_str())
+                .ok_or_else(|| {
+                    UpdateError::Config(format!("'{name}' profile not found in configuration file"))
+                        .into()
+                }),
+            None => self.connection_string.as_deref().ok_or_else(|| {
+                UpdateError::Config(
+                    "No connection string or default profile in configuration file".to_owned(),
+                )
+                .into()
+            }),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.validate_profiles()?;
+        self.validate_modes()
+    }
+
+    fn validate_profiles(&self) -> Result<()> {
+        if let Some(default_profile) = &self.default_profile
+            && !self.profiles.contains_key(default_profile)
+        {
+            return Err(anyhow!(
+                "Default profile '{default_profile}' not found in \"profiles\""
+            ));
+        }
+        if self.connection_string.is_none() && self.profiles.is_empty() {
+            return Err(anyhow!(
+                "Either \"connection_string\" or \"profiles\" must be specified"
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_modes(&self) -> Result<()> {
+        let default_modes: Vec<&str> = self
+            .update_mode
+            .iter()
+            .filter(|mode| mode.is_default)
+            .map(|mode| mode.name.as_str())
+            .collect();
+
+        match default_modes.len() {
+            0 => Err(anyhow!(
+                "No update mode is marked with \"is_default\": true"
+            )),
+            1 => Ok(()),
+            _ => Err(anyhow!(
+                "Only one update mode can be marked with \"is_default\": true, found {}",
+                default_modes.join(", ")
+            )),
+        }
+    }
+}
+
+// Path given with --config or POLYMIX_UPDATE_CONFIG is used as is,
+// otherwise the first existing of the standard locations
+pub fn locate(config_path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = config_path {
+        return Ok(path.to_owned());
+    }
+    if let Some(path) = env::var_os(CONFIG_ENV_VAR) {
+        return Ok(PathBuf::from(path));
+    }
+
+    let locations = standard_locations();
+    locations
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            UpdateError::Config(format!(
+                "Config file not found, looked in: {}",
+                locations
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .into()
+        })
+}
+
+// Current directory, directory of the executable and user config directory
+fn standard_locations() -> Vec<PathBuf> {
+    let mut locations = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        locations.push(exe_dir.join(CONFIG_FILE_NAME));
+    }
+    if let Some(config_dir) = dirs::config_dir() {
+        locations.push(config_dir.join("polymix-update").join(CONFIG_FILE_NAME));
+    }
+    locations
+}
+
 pub fn get<P: AsRef<Path>>(path: P) -> Result<Config> {
-    let file = File::open(path.as_ref())
-        .with_context(|| format!("Failed to open config file {}", path.as_ref().display()))?;
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| {
+        UpdateError::Config(format!("Failed to open config file {}", path.display()))
+    })?;
     let reader = BufReader::new(file);
-    let config = serde_json::from_reader(reader)?;
+    let config: Config = serde_json::from_reader(reader)
+        .with_context(|| UpdateError::Config(format!("Invalid config file {}", path.display())))?;
+    config
+        .validate()
+        .with_context(|| UpdateError::Config(format!("Invalid config file {}", path.display())))?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(defaults: &[bool]) -> Config {
+        Config {
+            connection_string: Some(String::new()),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            update_mode: defaults
+                .iter()
+                .enumerate()
+                .map(|(index, is_default)| UpdateMode {
+                    name: format!("mode{index}"),
+                    file_mask: "*.exe".to_owned(),
+                    is_default: *is_default,
+                    local_dir: None,
+                    case_sensitive: false,
+                    compression: Compression::None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn validate_requires_exactly_one_default_mode() {
+        assert!(config(&[false, true]).validate().is_ok());
+        assert!(config(&[false, false]).validate().is_err());
+        assert!(config(&[true, true]).validate().is_err());
+    }
+
+    fn config_with_profiles(default_profile: Option<&str>) -> Config {
+        let mut config = config(&[true]);
+        config.connection_string = None;
+        config.default_profile = default_profile.map(ToOwned::to_owned);
+        for name in ["test", "production"] {
+            config.profiles.insert(
+                name.to_owned(),
+                Profile {
+                    connection_string: format!("server={name}"),
+                },
+            );
+        }
+        config
+    }
+
+    #[test]
+    fn connection_string_uses_selected_or_default_profile() {
+        let config = config_with_profiles(Some("test"));
+        assert!(config.validate().is_ok());
+
+        assert_eq!(config.connection_string(None).unwrap(), "server=test");
+        assert_eq!(
+            config.connection_string(Some("production")).unwrap(),
+            "server=production"
+        );
+        assert!(config.connection_string(Some("staging")).is_err());
+    }
+
+    #[test]
+    fn validate_checks_profiles() {
+        assert!(config_with_profiles(Some("staging")).validate().is_err());
+
+        let config = config_with_profiles(None);
+        assert!(config.validate().is_ok());
+        assert!(config.connection_string(None).is_err());
+
+        let mut config = config_with_profiles(None);
+        config.profiles.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn locate_prefers_explicit_path() {
+        let path = Path::new("configs/test.json");
+        assert_eq!(locate(Some(path)).unwrap(), path);
+    }
+
+    #[test]
+    fn standard_locations_start_with_current_directory() {
+        let locations = standard_locations();
+
+        assert_eq!(locations[0], Path::new("settings.json"));
+        assert!(locations.len() > 1);
+    }
+
+    #[test]
+    fn find_mode_ignores_case() {
+        let config = config(&[false, true]);
+
+        assert_eq!(config.find_mode("MODE0").unwrap().name, "mode0");
+        assert_eq!(config.default_mode().unwrap().name, "mode1");
+        assert!(config.find_mode("xls").is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::error::UpdateError;
+
+pub const SQL_USER_ENV_VAR: &str = "POLYMIX_SQL_USER";
+pub const SQL_PASSWORD_ENV_VAR: &str = "POLYMIX_SQL_PASSWORD";
+
+// SQL Server credentials, also the format of the file given with --credentials-file
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Credentials {
+    // User and password always come from the same source, so that a password
+    // meant for another account is never sent with this user name
+    fn or(self, other: Credentials) -> Credentials {
+        if self.user.is_some() || self.password.is_some() {
+            self
+        } else {
+            other
+        }
+    }
+}
+
+// Command line flags take precedence over the credentials file, which takes precedence
+// over environment variables. Password is asked for when the source giving the user name
+// has no password.
+pub fn resolve(command_line: Credentials, credentials_file: Option<&Path>) -> Result<Credentials> {
+    let from_file = match credentials_file {
+        Some(path) => read_file(path)?,
+        None => Credentials::default(),
+    };
+    let from_env = Credentials {
+        user: env::var(SQL_USER_ENV_VAR).ok(),
+        password: env::var(SQL_PASSWORD_ENV_VAR).ok(),
+    };
+
+    let credentials = command_line.or(from_file).or(from_env);
+
+    match (&credentials.user, &credentials.password) {
+        (Some(user), None) => {
+            let password = rpassword::prompt_password(format!("SQL Server password for {user}: "))
+                .context(UpdateError::InvalidArguments(
+                    "Failed to read SQL Server password".to_owned(),
+                ))?;
+            Ok(Credentials {
+                password: Some(password),
+                ..credentials
+            })
+        }
+        (None, Some(_)) => Err(UpdateError::InvalidArguments(
+            "SQL Server password is given without user name".to_owned(),
+        )
+        .into()),
+        _ => Ok(credentials),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Credentials> {
+    let file = File::open(path).with_context(|| {
+        UpdateError::Config(format!(
+            "Failed to open credentials file {}",
+            path.display()
+        ))
+    })?;
+    let reader = BufReader::new(file);
+    serde_json::from_reader(reader).with_context(|| {
+        UpdateError::Config(format!("Invalid credentials file {}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(user: Option<&str>, password: Option<&str>) -> Credentials {
+        Credentials {
+            user: user.map(ToOwned::to_owned),
+            password: password.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn or_takes_user_and_password_from_one_source() {
+        let combined = credentials(Some("sa"), None).or(credentials(Some("user"), Some("secret")));
+        assert_eq!(combined, credentials(Some("sa"), None));
+
+        let combined = credentials(None, None).or(credentials(Some("user"), Some("secret")));
+        assert_eq!(combined, credentials(Some("user"), Some("secret")));
+    }
+
+    #[test]
+    fn read_file_parses_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        std::fs::write(&path, r#"{ "user": "sa", "password": "secret" }"#).unwrap();
+
+        assert_eq!(
+            read_file(&path).unwrap(),
+            credentials(Some("sa"), Some("secret"))
+        );
+    }
+
+    #[test]
+    fn resolve_uses_command_line_credentials() {
+        let resolved = resolve(credentials(Some("sa"), Some("secret")), None).unwrap();
+
+        assert_eq!(resolved, credentials(Some("sa"), Some("secret")));
+    }
+}
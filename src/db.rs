@@ -1,10 +1,17 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result};
 use chrono::{DateTime, NaiveDateTime, Utc};
-use tiberius::FromSql;
+use tiberius::{FromSql, ToSql};
 use tiberius::AuthMethod;
 use tiberius::{Client, Config};
 use tiberius::{Row, SqlBrowser};
+use std::io::{Read, Write};
+use std::mem;
 use tokio::net::TcpStream;
+
+use crate::compression::Compression;
+use crate::error::UpdateError;
+use crate::output::{progress, progressln};
+use crate::schema::{Migration, SCHEMA_TABLE};
 use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
 
 pub type SqlConnection = Client<Compat<TcpStream>>;
@@ -16,7 +23,42 @@ pub struct DB {
 pub struct DBFile {
     pub name: String,
     pub date: NaiveDateTime,
-    pub content: Option<Vec<u8>>,
+    pub size: Option<i64>, // uncompressed file length, the stored image may be shorter; null when image is null
+    pub hash: Option<String>, // hex encoded SHA-256 of the uncompressed file, null for rows uploaded by older versions
+    pub uploaded_by: Option<String>,
+    pub upload_host: Option<String>,
+    pub upload_date: Option<NaiveDateTime>, // UTC
+}
+
+// OS user and machine the upload is made from, stored with every version
+pub struct UploadSource {
+    pub user: String,
+    pub host: String,
+}
+
+// File being uploaded, its content is streamed separately.
+// Size and hash are those of the uncompressed content.
+pub struct FileUpload<'a> {
+    pub name: &'a str,
+    pub date: DateTime<Utc>,
+    pub size: i64,
+    pub hash: &'a str,
+    pub compression: Compression,
+    pub source: &'a UploadSource,
+}
+
+// File images are transferred in chunks of this size, so that memory use
+// does not depend on file size
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// One stored version of a file, PolyCalcVersion always contains a copy of the current one
+pub struct DBFileVersion {
+    pub version: i32,
+    pub date: NaiveDateTime,
+    pub hash: Option<String>,
+    pub uploaded_by: Option<String>,
+    pub upload_date: Option<NaiveDateTime>,
+    pub is_current: bool,
 }
 
 impl DB {
@@ -25,7 +67,8 @@ impl DB {
         sql_username: Option<&str>,
         sql_password: Option<&str>,
     ) -> Result<DB> {
-        let mut config = Config::from_ado_string(connection_string)?;
+        let mut config = Config::from_ado_string(connection_string)
+            .context(UpdateError::Config("Invalid connection string".to_owned()))?;
 
         match (sql_username, sql_password) {
             (Some(username), Some(password)) => {
@@ -33,66 +76,342 @@ impl DB {
             }
             (None, None) => {}
             _ => {
-                return Err(anyhow!(
-                    "Both SQL username and password are required for SQL authentication"
-                ));
+                return Err(UpdateError::InvalidArguments(
+                    "Both SQL username and password are required for SQL authentication".to_owned(),
+                )
+                .into());
             }
         }
 
-        println!("Connecting to server {}", config.get_addr());
+        progressln!("Connecting to server {}", config.get_addr());
 
-        let tcp = TcpStream::connect_named(&config).await?;
-        tcp.set_nodelay(true)?;
+        let connection_error = || {
+            UpdateError::Connection(format!("Failed to connect to server {}", config.get_addr()))
+        };
+        let tcp = TcpStream::connect_named(&config)
+            .await
+            .with_context(connection_error)?;
+        tcp.set_nodelay(true).with_context(connection_error)?;
 
-        let client = Client::connect(config.clone(), tcp.compat_write()).await?;
+        let client = Client::connect(config.clone(), tcp.compat_write())
+            .await
+            .with_context(connection_error)?;
 
         Ok(DB { client })
     }
 
+    // Transaction statements are sent as plain batches, sp_executesql does not allow
+    // a transaction to stay open after it returns
+    pub async fn begin_transaction(&mut self) -> Result<()> {
+        self.client
+            .simple_query("BEGIN TRANSACTION")
+            .await?
+            .into_results()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn commit_transaction(&mut self) -> Result<()> {
+        progress!("Committing changes...");
+        self.client
+            .simple_query("COMMIT TRANSACTION")
+            .await?
+            .into_results()
+            .await?;
+        progressln!("OK");
+        Ok(())
+    }
+
+    // Transaction may have been rolled back already by a failed statement
+    pub async fn rollback_transaction(&mut self) -> Result<()> {
+        self.client
+            .simple_query("IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION")
+            .await?
+            .into_results()
+            .await?;
+        Ok(())
+    }
+
+    // Collation of PolyCalcVersion.FileName, it decides how names are compared in SQL
+    pub async fn get_file_name_collation(&mut self) -> Result<Option<String>> {
+        let row = self
+            .client
+            .query(
+                "select collation_name AS Collation from sys.columns
+                 where object_id = OBJECT_ID(N'PolyCalcVersion') and name = N'FileName'",
+                &[],
+            )
+            .await?
+            .into_row()
+            .await?;
+
+        Ok(match row {
+            Some(row) => Self::try_get_string(&row, "Collation"),
+            None => None,
+        })
+    }
+
+    // Highest applied schema version, 0 for a database never initialized by this tool
+    pub async fn get_schema_version(&mut self) -> Result<i32> {
+        let row = self
+            .client
+            .query(
+                format!(
+                    "IF OBJECT_ID(N'{SCHEMA_TABLE}', N'U') IS NULL SELECT 0 AS Version
+                     ELSE SELECT ISNULL(MAX(Version), 0) AS Version FROM {SCHEMA_TABLE}"
+                ),
+                &[],
+            )
+            .await?
+            .into_row()
+            .await?
+            .ok_or_else(|| UpdateError::Database("Failed to read schema version".to_owned()))?;
+
+        Self::try_get_not_nullable(&row, "Version")
+    }
+
+    // Each step is applied and recorded in its own transaction, XACT_ABORT rolls it back
+    // when any statement fails
+    pub async fn apply_migration(&mut self, migration: &Migration) -> Result<()> {
+        progress!("Applying schema version {} ({})...", migration.version, migration.description);
+        self.client
+            .simple_query(format!(
+                "IF OBJECT_ID(N'{SCHEMA_TABLE}', N'U') IS NULL
+                     CREATE TABLE {SCHEMA_TABLE} (Version int NOT NULL PRIMARY KEY, AppliedDate datetime NOT NULL)"
+            ))
+            .await?
+            .into_results()
+            .await?;
+        self.client
+            .simple_query(format!(
+                "SET XACT_ABORT ON;
+                 BEGIN TRANSACTION;
+                 {};
+                 INSERT INTO {SCHEMA_TABLE} (Version, AppliedDate) VALUES ({}, GETUTCDATE());
+                 COMMIT TRANSACTION;",
+                migration.sql, migration.version
+            ))
+            .await?
+            .into_results()
+            .await?;
+        progressln!("OK");
+        Ok(())
+    }
+
     pub async fn insert_file_with_content(
         &mut self,
-        file_name: &str,
-        file_date: DateTime<Utc>,
-        content: &[u8],
+        file: &FileUpload<'_>,
+        content: impl Read,
     ) -> Result<()> {
-        print!("Adding new file...");
+        progress!("Adding new file...");
+
+        let version = self.add_file_version(file, content).await?;
 
         self.client
             .execute(
-                "INSERT INTO PolyCalcVersion (FileName, FileDate, FileImage) VALUES (@P1, @P2, @P3)",
-                &[&file_name, &file_date, &content],
+                "INSERT INTO PolyCalcVersion (FileName, FileDate, FileSize, FileHash, FileImage, Compression, UploadedBy, UploadHost, UploadDate)
+                 SELECT FileName, FileDate, FileSize, FileHash, FileImage, Compression, UploadedBy, UploadHost, UploadDate FROM PolyCalcVersionHistory
+                 WHERE FileName = @P1 AND IsCurrent = 1",
+                &[&file.name],
             )
             .await?;
 
-        println!("OK");
+        progressln!("OK, version {version}");
 
         Ok(())
     }
 
     pub async fn update_file_content(
+        &mut self,
+        file: &FileUpload<'_>,
+        content: impl Read,
+    ) -> Result<()> {
+        progress!("Updating file content...");
+
+        // Files stored before version history was introduced have no history rows,
+        // keep their current image as the first version, so that it is not lost
+        self.client
+            .execute(
+                "IF NOT EXISTS (SELECT 1 FROM PolyCalcVersionHistory WHERE FileName = @P1)
+                 INSERT INTO PolyCalcVersionHistory (FileName, Version, FileDate, FileSize, FileHash, FileImage, Compression, UploadedBy, UploadHost, UploadDate, IsCurrent)
+                 SELECT FileName, 1, FileDate, ISNULL(FileSize, DATALENGTH(FileImage)), FileHash, FileImage, Compression, UploadedBy, UploadHost, UploadDate, 1
+                 FROM PolyCalcVersion WHERE FileName = @P1",
+                &[&file.name],
+            )
+            .await?;
+
+        let version = self.add_file_version(file, content).await?;
+
+        self.update_from_current_version(file.name).await?;
+
+        progressln!("OK, version {version}");
+
+        Ok(())
+    }
+
+    // Adds a new version of the file to history and makes it current, returns the version number
+    async fn add_file_version(&mut self, file: &FileUpload<'_>, content: impl Read) -> Result<i32> {
+        let row = self
+            .client
+            .query(
+                "UPDATE PolyCalcVersionHistory SET IsCurrent = 0 WHERE FileName = @P1 AND IsCurrent = 1;
+                 INSERT INTO PolyCalcVersionHistory (FileName, Version, FileDate, FileSize, FileHash, FileImage, Compression, UploadedBy, UploadHost, UploadDate, IsCurrent)
+                 OUTPUT inserted.Version
+                 SELECT @P1, ISNULL(MAX(Version), 0) + 1, @P2, @P3, @P4, 0x, @P5, @P6, @P7, GETUTCDATE(), 1
+                 FROM PolyCalcVersionHistory WHERE FileName = @P1",
+                &[
+                    &file.name,
+                    &file.date,
+                    &file.size,
+                    &file.hash,
+                    &file.compression.marker(),
+                    &file.source.user.as_str(),
+                    &file.source.host.as_str(),
+                ],
+            )
+            .await?
+            .into_row()
+            .await?
+            .ok_or_else(|| {
+                UpdateError::Database(format!("Failed to add new version of {}", file.name))
+            })?;
+        let version = Self::try_get_not_nullable(&row, "Version")?;
+
+        self.write_file_image(file, version, content).await?;
+
+        Ok(version)
+    }
+
+    // Compresses the content while reading it and appends the image to the history row
+    // chunk by chunk
+    async fn write_file_image(
+        &mut self,
+        file: &FileUpload<'_>,
+        version: i32,
+        mut content: impl Read,
+    ) -> Result<()> {
+        let mut encoder = file.compression.encoder(Vec::with_capacity(CHUNK_SIZE))?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = content.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            encoder.write_all(&buffer[..read])?;
+            if encoder.get_mut().len() >= CHUNK_SIZE {
+                let chunk = mem::take(encoder.get_mut());
+                self.append_file_image(file.name, version, &chunk).await?;
+            }
+        }
+
+        let chunk = encoder.finish()?;
+        if !chunk.is_empty() {
+            self.append_file_image(file.name, version, &chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn append_file_image(
         &mut self,
         file_name: &str,
-        file_date: DateTime<Utc>,
-        content: &[u8],
+        version: i32,
+        chunk: &[u8],
     ) -> Result<()> {
-        print!("Updating file content...");
+        self.client
+            .execute(
+                "UPDATE PolyCalcVersionHistory SET FileImage.WRITE(@P3, NULL, 0)
+                 WHERE FileName = @P1 AND Version = @P2",
+                &[&file_name, &version, &chunk],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Removes the file together with all its stored versions
+    pub async fn delete_file(&mut self, file_name: &str) -> Result<()> {
+        self.client
+            .execute(
+                "DELETE FROM PolyCalcVersionHistory WHERE FileName = @P1;
+                 DELETE FROM PolyCalcVersion WHERE FileName = @P1",
+                &[&file_name],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // Makes the stored version current again and restores its image in PolyCalcVersion
+    pub async fn set_current_version(&mut self, file_name: &str, version: i32) -> Result<()> {
+        progress!("Restoring version {version}...");
+
+        // history and PolyCalcVersion have to agree on the current version
+        self.begin_transaction().await?;
+        let result = self.restore_version(file_name, version).await;
+        match result {
+            Ok(()) => self.commit_transaction().await?,
+            Err(error) => {
+                if let Err(rollback_error) = self.rollback_transaction().await {
+                    progressln!("\nRollback failed: {rollback_error}");
+                }
+                return Err(error);
+            }
+        }
+
+        progressln!("OK");
+
+        Ok(())
+    }
 
+    async fn restore_version(&mut self, file_name: &str, version: i32) -> Result<()> {
         self.client
             .execute(
-                "UPDATE PolyCalcVersion set FileDate = @P1, FileImage = @P2 WHERE FileName = @P3",
-                &[&file_date, &content, &file_name],
+                "UPDATE PolyCalcVersionHistory SET IsCurrent = CASE WHEN Version = @P2 THEN 1 ELSE 0 END
+                 WHERE FileName = @P1",
+                &[&file_name, &version],
             )
             .await?;
 
-        println!("OK");
+        self.update_from_current_version(file_name).await
+    }
+
+    // Copies the current history version of the file into PolyCalcVersion
+    async fn update_from_current_version(&mut self, file_name: &str) -> Result<()> {
+        self.client
+            .execute(
+                "UPDATE f SET FileDate = h.FileDate, FileSize = h.FileSize, FileHash = h.FileHash, FileImage = h.FileImage, Compression = h.Compression,
+                     UploadedBy = h.UploadedBy, UploadHost = h.UploadHost, UploadDate = h.UploadDate
+                 FROM PolyCalcVersion f
+                 JOIN PolyCalcVersionHistory h ON h.FileName = f.FileName AND h.IsCurrent = 1
+                 WHERE f.FileName = @P1",
+                &[&file_name],
+            )
+            .await?;
 
         Ok(())
     }
 
-    pub async fn get_db_files(&mut self) -> Result<Vec<DBFile>> {
+    // Files with names matching any of the LIKE patterns, all files when there are none.
+    // FileName is compared as it is, so that the primary key index can be used, with the
+    // default collation case is ignored.
+    pub async fn get_db_files(&mut self, like_patterns: &[String]) -> Result<Vec<DBFile>> {
+        let mut query = "select FileName, FileDate, FileHash, UploadedBy, UploadHost, UploadDate,
+                     ISNULL(FileSize, CAST(DATALENGTH(FileImage) AS bigint)) AS FileSize
+                 from PolyCalcVersion"
+            .to_owned();
+        if !like_patterns.is_empty() {
+            let conditions: Vec<String> = (1..=like_patterns.len())
+                .map(|index| format!("FileName LIKE @P{index}"))
+                .collect();
+            query.push_str(&format!(" where {}", conditions.join(" or ")));
+        }
+        let params: Vec<&dyn ToSql> = like_patterns
+            .iter()
+            .map(|pattern| pattern as &dyn ToSql)
+            .collect();
+
         let rows = self
             .client
-            .query("select FileName, FileDate from PolyCalcVersion", &[])
+            .query(query, &params)
             .await?
             .into_first_result()
             .await?;
@@ -102,6 +421,21 @@ impl DB {
         Ok(result)
     }
 
+    pub async fn get_file_versions(&mut self, file_name: &str) -> Result<Vec<DBFileVersion>> {
+        let rows = self
+            .client
+            .query(
+                "select Version, FileDate, FileHash, UploadedBy, UploadDate, IsCurrent
+                 from PolyCalcVersionHistory where FileName = @P1 order by Version",
+                &[&file_name],
+            )
+            .await?
+            .into_first_result()
+            .await?;
+
+        rows.iter().map(Self::try_map_db_file_version).collect()
+    }
+
     // pub async fn get_db_files_with_content(&mut self) -> Result<Vec<DBFile>> {
     //     let rows = self
     //         .client
@@ -118,18 +452,54 @@ impl DB {
     //     Ok(result)
     // }
 
-    pub async fn get_db_file_content(&mut self, file_name: &str) -> Result<Option<Vec<u8>>> {
-        self.client
+    // Writes the content of the file, decompressed when the image is stored compressed.
+    // The image is read in chunks, nothing is written when it is null.
+    pub async fn read_file_content(&mut self, file_name: &str, writer: impl Write) -> Result<()> {
+        let row = self
+            .client
             .query(
-                "select FileImage from PolyCalcVersion where FileName = @P1",
+                "select Compression, CAST(DATALENGTH(FileImage) AS bigint) AS ImageSize
+                 from PolyCalcVersion where FileName = @P1",
                 &[&file_name],
             )
             .await?
-            .into_first_result()
+            .into_row()
             .await?
-            .first()
-            .ok_or_else(|| anyhow!("File not found: {file_name}"))
-            .and_then(|row| Self::try_get_binary(row, "FileImage"))
+            .ok_or_else(|| UpdateError::NotFound(format!("File not found: {file_name}")))?;
+        let compression = Compression::from_marker(row.try_get("Compression")?)?;
+        let image_size: i64 = row.try_get("ImageSize")?.unwrap_or_default();
+
+        let mut decoder = compression.decoder(writer)?;
+        let chunk_size = CHUNK_SIZE as i64;
+        // SUBSTRING positions start from 1
+        let mut position: i64 = 1;
+        while position <= image_size {
+            let row = self
+                .client
+                .query(
+                    "select SUBSTRING(FileImage, @P2, @P3) AS Chunk
+                     from PolyCalcVersion where FileName = @P1",
+                    &[&file_name, &position, &chunk_size],
+                )
+                .await?
+                .into_row()
+                .await?;
+            let chunk = match &row {
+                Some(row) => row.try_get::<&[u8], _>("Chunk")?.unwrap_or_default(),
+                None => &[],
+            };
+            if chunk.is_empty() {
+                return Err(UpdateError::Database(format!(
+                    "File {file_name} was changed while downloading"
+                ))
+                .into());
+            }
+            decoder.write_all(chunk)?;
+            position += chunk.len() as i64;
+        }
+        decoder.finish()?;
+
+        Ok(())
     }
 
     fn map_db_files(rows: &[Row]) -> Result<Vec<DBFile>> {
@@ -140,7 +510,22 @@ impl DB {
         Ok(DBFile {
             name: Self::try_get_string(row, "FileName").unwrap_or_default(),
             date: Self::try_get_not_nullable(row, "FileDate")?,
-            content: Self::try_get_binary(row, "FileImage").unwrap_or_default(), // this field is not always in result set
+            size: row.try_get("FileSize")?,
+            hash: Self::try_get_string(row, "FileHash"),
+            uploaded_by: Self::try_get_string(row, "UploadedBy"),
+            upload_host: Self::try_get_string(row, "UploadHost"),
+            upload_date: row.try_get("UploadDate")?,
+        })
+    }
+
+    fn try_map_db_file_version(row: &Row) -> Result<DBFileVersion> {
+        Ok(DBFileVersion {
+            version: Self::try_get_not_nullable(row, "Version")?,
+            date: Self::try_get_not_nullable(row, "FileDate")?,
+            hash: Self::try_get_string(row, "FileHash"),
+            uploaded_by: Self::try_get_string(row, "UploadedBy"),
+            upload_date: row.try_get("UploadDate")?,
+            is_current: Self::try_get_not_nullable(row, "IsCurrent")?,
         })
     }
 
@@ -155,11 +540,8 @@ impl DB {
     // Tries to unwrap value of not-nullable field, returns error when finds unexpected null
     fn try_get_not_nullable<'a, R: FromSql<'a>>(row: &'a Row, col: &str) -> Result<R> {
         row.try_get(col)?
-            .ok_or_else(|| anyhow!("Null value not expected in column {col}"))
-    }
-
-    fn try_get_binary(row: &Row, col: &str) -> Result<Option<Vec<u8>>> {
-        let data = row.try_get::<&[u8], _>(col)?;
-        Ok(data.map(Into::into))
+            .ok_or_else(|| {
+                UpdateError::Database(format!("Null value not expected in column {col}")).into()
+            })
     }
 }
@@ -0,0 +1,107 @@
+use std::io;
+use thiserror::Error;
+
+// Exit codes are part of the command line interface, scripts rely on them
+pub const EXIT_INVALID_ARGUMENTS: i32 = 1;
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_MODE_NOT_FOUND: i32 = 3;
+pub const EXIT_CONNECTION: i32 = 4;
+pub const EXIT_DATABASE: i32 = 5;
+pub const EXIT_LOCAL_FILE: i32 = 6;
+pub const EXIT_VERIFICATION: i32 = 7;
+pub const EXIT_NOT_FOUND: i32 = 8;
+pub const EXIT_INTERRUPTED: i32 = 9;
+pub const EXIT_OTHER: i32 = 10;
+pub const EXIT_SCHEMA_OUTDATED: i32 = 11;
+
+// Failures scripts may want to tell apart. They are usually attached as context
+// to the underlying error, so the original cause is still reported.
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    #[error("{0}")]
+    InvalidArguments(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("'{0}' update mode not found in configuration file")]
+    ModeNotFound(String),
+    #[error("{0}")]
+    Connection(String),
+    #[error("{0}")]
+    Database(String),
+    #[error("{0}")]
+    LocalFile(String),
+    #[error("{0}")]
+    Verification(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Interrupted(String),
+    #[error("{0}")]
+    SchemaOutdated(String),
+}
+
+impl UpdateError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            UpdateError::InvalidArguments(_) => EXIT_INVALID_ARGUMENTS,
+            UpdateError::Config(_) => EXIT_CONFIG,
+            UpdateError::ModeNotFound(_) => EXIT_MODE_NOT_FOUND,
+            UpdateError::Connection(_) => EXIT_CONNECTION,
+            UpdateError::Database(_) => EXIT_DATABASE,
+            UpdateError::LocalFile(_) => EXIT_LOCAL_FILE,
+            UpdateError::Verification(_) => EXIT_VERIFICATION,
+            UpdateError::NotFound(_) => EXIT_NOT_FOUND,
+            UpdateError::Interrupted(_) => EXIT_INTERRUPTED,
+            UpdateError::SchemaOutdated(_) => EXIT_SCHEMA_OUTDATED,
+        }
+    }
+}
+
+// The outermost typed error decides, untyped database and I/O errors get their own codes
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    if let Some(error) = error.downcast_ref::<UpdateError>() {
+        error.exit_code()
+    } else if error.downcast_ref::<tiberius::error::Error>().is_some() {
+        EXIT_DATABASE
+    } else if error.downcast_ref::<io::Error>().is_some() {
+        EXIT_LOCAL_FILE
+    } else {
+        EXIT_OTHER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn exit_code_of_typed_error() {
+        let error = anyhow::Error::from(UpdateError::ModeNotFound("main".to_owned()));
+
+        assert_eq!(exit_code(&error), EXIT_MODE_NOT_FOUND);
+        assert_eq!(
+            error.to_string(),
+            "'main' update mode not found in configuration file"
+        );
+    }
+
+    #[test]
+    fn exit_code_of_typed_context() {
+        let error = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+            .context(UpdateError::Config("Failed to open config file".to_owned()))
+            .context("Startup failed")
+            .unwrap_err();
+
+        assert_eq!(exit_code(&error), EXIT_CONFIG);
+    }
+
+    #[test]
+    fn exit_code_of_untyped_errors() {
+        let error = anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Failed to write calc.exe");
+        assert_eq!(exit_code(&error), EXIT_LOCAL_FILE);
+
+        assert_eq!(exit_code(&anyhow!("Something else")), EXIT_OTHER);
+    }
+}
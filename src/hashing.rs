@@ -0,0 +1,81 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+// Calculates SHA-256 and size of the data read or written through it,
+// so that files are hashed while they are streamed
+pub struct Hashing<T> {
+    inner: T,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<T> Hashing<T> {
+    pub fn new(inner: T) -> Hashing<T> {
+        Hashing {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    // Returns the inner reader or writer, size and hex encoded hash,
+    // the same form as stored in FileHash column
+    pub fn finish(self) -> (T, u64, String) {
+        (
+            self.inner,
+            self.size,
+            format!("{:x}", self.hasher.finalize()),
+        )
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Size and hash of everything the reader returns
+pub fn hash_reader(reader: impl Read) -> io::Result<(u64, String)> {
+    let mut hashing = Hashing::new(reader);
+    io::copy(&mut hashing, &mut io::sink())?;
+    let (_, size, hash) = hashing.finish();
+    Ok((size, hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_HASH: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn hash_reader_returns_lowercase_hex_sha256() {
+        assert_eq!(hash_reader(&b"abc"[..]).unwrap(), (3, ABC_HASH.to_owned()));
+    }
+
+    #[test]
+    fn hashing_writer_passes_data_through() {
+        let mut writer = Hashing::new(vec![]);
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"bc").unwrap();
+
+        assert_eq!(writer.finish(), (b"abc".to_vec(), 3, ABC_HASH.to_owned()));
+    }
+}
@@ -1,79 +1,135 @@
 mod command;
+mod compression;
 mod config;
+mod credentials;
 mod db;
+mod error;
+mod hashing;
+mod names;
+mod output;
+mod schema;
+mod staging;
+mod status;
 mod updater;
 
 use anyhow::{Result, anyhow};
-use std::{env, process, str::FromStr};
+use std::{env, path::PathBuf, process, str::FromStr};
 
-use command::Command;
-use config::get;
-use updater::Updater;
+use command::{Command, RollbackTarget, SortOrder};
+use config::{CONFIG_ENV_VAR, get, locate};
+use credentials::{Credentials, SQL_PASSWORD_ENV_VAR, SQL_USER_ENV_VAR};
+use output::{OutputFormat, progressln};
+use updater::{Options, Updater};
 
 #[derive(Debug)]
 struct ParsedArgs {
     command: Command,
-    mode: String,
+    mode: Option<String>,
     sql_username: Option<String>,
     sql_password: Option<String>,
+    only_newer: bool,
+    rollback_target: RollbackTarget,
+    dry_run: bool,
+    mask: Option<String>,
+    assume_yes: bool,
+    dir: Option<PathBuf>,
+    config_path: Option<PathBuf>,
+    verbose: bool,
+    profile: Option<String>,
+    credentials_file: Option<PathBuf>,
+    format: OutputFormat,
+    sort: SortOrder,
 }
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    run().await?;
-    Ok(())
+async fn main() {
+    if let Err(error) = run().await {
+        eprintln!("Error: {error:#}");
+        process::exit(error::exit_code(&error));
+    }
 }
 
 async fn run() -> Result<()> {
-    println!(
+    let args: Vec<_> = env::args().collect();
+    let parsed_args = parse_args(&args);
+    if let Ok(parsed_args) = &parsed_args {
+        output::init(parsed_args.format);
+    }
+
+    progressln!(
         "PolyMix Updater v0.2  (c) 2021-2026 PolyMix Development Group.\nUse to work (update, download, etc.) with file images stored in database.\n"
     );
-    let args: Vec<_> = env::args().collect();
-    let parsed_args = match parse_args(&args) {
+    let parsed_args = match parsed_args {
         Ok(parsed_args) => parsed_args,
         Err(error) => {
             println!("{error}");
             println!();
             print_usage();
-            process::exit(1);
+            process::exit(error::EXIT_INVALID_ARGUMENTS);
         }
     };
 
     // try reading configuration
-    let config = get("settings.json");
-    match config {
-        Ok(config) => {
-            Updater::new(
-                &config,
-                parsed_args.command,
-                &parsed_args.mode,
-                parsed_args.sql_username,
-                parsed_args.sql_password,
-            )
-            .run()
-            .await?;
-        }
-        Err(error) => {
-            println!("{error}");
-            process::exit(2);
-        }
+    let config_path = locate(parsed_args.config_path.as_deref())?;
+    if parsed_args.verbose {
+        progressln!("Using config file {}", config_path.display());
     }
+    let config = get(config_path)?;
 
-    Ok(())
+    let credentials = credentials::resolve(
+        Credentials {
+            user: parsed_args.sql_username,
+            password: parsed_args.sql_password,
+        },
+        parsed_args.credentials_file.as_deref(),
+    )?;
+    Updater::new(
+        &config,
+        parsed_args.command,
+        parsed_args.mode,
+        Options {
+            sql_username: credentials.user,
+            sql_password: credentials.password,
+            only_newer: parsed_args.only_newer,
+            rollback_target: parsed_args.rollback_target,
+            dry_run: parsed_args.dry_run,
+            mask: parsed_args.mask,
+            assume_yes: parsed_args.assume_yes,
+            dir: parsed_args.dir,
+            profile: parsed_args.profile,
+            format: parsed_args.format,
+            sort: parsed_args.sort,
+        },
+    )
+    .run()
+    .await
 }
 
 fn parse_args(args: &[String]) -> Result<ParsedArgs> {
-    if args.len() < 3 {
+    if args.len() < 2 {
         return Err(anyhow!("Not enough arguments."));
     }
 
     let command = Command::from_str(&args[1]).map_err(|()| anyhow!("Unknown command '{}'.", args[1]))?;
-    let mode = args[2].clone();
+    // mode is optional, default one from configuration is used when omitted
+    let mode = args.get(2).filter(|arg| !arg.starts_with('-')).cloned();
 
     let mut sql_username: Option<String> = None;
     let mut sql_password: Option<String> = None;
+    let mut only_newer = false;
+    let mut dry_run = false;
+    let mut mask: Option<String> = None;
+    let mut assume_yes = false;
+    let mut dir: Option<PathBuf> = None;
+    let mut config_path: Option<PathBuf> = None;
+    let mut verbose = false;
+    let mut profile: Option<String> = None;
+    let mut credentials_file: Option<PathBuf> = None;
+    let mut format: Option<OutputFormat> = None;
+    let mut sort: Option<SortOrder> = None;
+    let mut rollback_target: Option<RollbackTarget> = None;
 
-    let mut index = 3;
+    let mut index = if mode.is_some() { 3 } else { 2 };
     while index < args.len() {
         let arg = &args[index];
 
@@ -118,41 +174,190 @@ fn parse_args(args: &[String]) -> Result<ParsedArgs> {
                     return Err(anyhow!("Flag '--sql-password' was provided more than once."));
                 }
             }
+            "--newer" => only_newer = true,
+            "--dry-run" => dry_run = true,
+            "--yes" | "-y" => assume_yes = true,
+            "--verbose" | "-v" => verbose = true,
+            "--credentials-file" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--credentials-file' requires a value."))?;
+                if credentials_file.replace(PathBuf::from(value)).is_some() {
+                    return Err(anyhow!(
+                        "Flag '--credentials-file' was provided more than once."
+                    ));
+                }
+            }
+            "--format" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--format' requires a value."))?;
+                let value = OutputFormat::from_str(value).map_err(|()| {
+                    anyhow!("Flag '--format' must be one of text, json or csv, got '{value}'.")
+                })?;
+                if format.replace(value).is_some() {
+                    return Err(anyhow!("Flag '--format' was provided more than once."));
+                }
+            }
+            "--sort" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--sort' requires a value."))?;
+                let value = SortOrder::from_str(value).map_err(|()| {
+                    anyhow!("Flag '--sort' must be one of name, date or size, got '{value}'.")
+                })?;
+                if sort.replace(value).is_some() {
+                    return Err(anyhow!("Flag '--sort' was provided more than once."));
+                }
+            }
+            "--profile" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--profile' requires a value."))?;
+                if profile.replace(value.to_string()).is_some() {
+                    return Err(anyhow!("Flag '--profile' was provided more than once."));
+                }
+            }
+            "--config" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--config' requires a value."))?;
+                if config_path.replace(PathBuf::from(value)).is_some() {
+                    return Err(anyhow!("Flag '--config' was provided more than once."));
+                }
+            }
+            "--dir" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--dir' requires a value."))?;
+                if dir.replace(PathBuf::from(value)).is_some() {
+                    return Err(anyhow!("Flag '--dir' was provided more than once."));
+                }
+            }
+            "--mask" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--mask' requires a value."))?;
+                if mask.replace(value.to_string()).is_some() {
+                    return Err(anyhow!("Flag '--mask' was provided more than once."));
+                }
+            }
+            "--to" => {
+                index += 1;
+                let value = args
+                    .get(index)
+                    .ok_or_else(|| anyhow!("Flag '--to' requires a value."))?;
+                let target = RollbackTarget::from_str(value).map_err(|()| {
+                    anyhow!("Flag '--to' requires a version number or a date, got '{value}'.")
+                })?;
+                if rollback_target.replace(target).is_some() {
+                    return Err(anyhow!("Flag '--to' was provided more than once."));
+                }
+            }
             _ => return Err(anyhow!("Unknown argument '{arg}'.")),
         }
 
         index += 1;
     }
 
-    if sql_username.is_some() ^ sql_password.is_some() {
+    if mode.is_some() && mask.is_some() {
         return Err(anyhow!(
-            "Both '--sql-user' and '--sql-password' must be provided together."
+            "Either mode or '--mask' can be provided, not both."
         ));
     }
 
+    // password is asked for when only user is given
+    if sql_password.is_some() && sql_username.is_none() {
+        return Err(anyhow!("Flag '--sql-password' requires '--sql-user'."));
+    }
+
     Ok(ParsedArgs {
         command,
         mode,
         sql_username,
         sql_password,
+        only_newer,
+        rollback_target: rollback_target.unwrap_or_default(),
+        dry_run,
+        mask,
+        assume_yes,
+        dir,
+        config_path,
+        verbose,
+        profile,
+        credentials_file,
+        format: format.unwrap_or_default(),
+        sort: sort.unwrap_or_default(),
     })
 }
 
 fn print_usage() {
     println!(
         "USAGE:
-    	polymix-update [COMMAND] [mode] [FLAGS]\n"
+    	polymix-update [COMMAND] [mode] [FLAGS]
+    	mode is optional, the one marked with \"is_default\" in settings.json is used when omitted\n"
     );
     println!(
         "COMMANDS:
     \tupload\t\tWrite files from current directory to database
     \tdownload\tRead files from database and store in current directory
-    \tlist\t\tlist files stored in database\n"
+    \tlist\t\tlist files stored in database with their size, uploader and hash
+    \thistory\t\tlist stored versions of a file, pass file name instead of mode
+    \trollback\tmake previous (or --to) version of a file current, pass file name instead of mode
+    \tstatus\t\tcompare files in current directory with files stored in database
+    \tdelete\t\tremove files and their versions from database, asks for confirmation
+    \tinit\t\tcreate database tables or upgrade them to the current schema\n"
     );
     println!(
         "FLAGS:
-    	--sql-user, --user <name>\t\tSQL Server user name (requires --sql-password)
-    	--sql-password, --password <value>\tSQL Server password (requires --sql-user)\n"
+    	--sql-user, --user <name>\t\tSQL Server user name, password is asked for when not given
+    	--sql-password, --password <value>\tSQL Server password (requires --sql-user)
+    	--credentials-file <path>\t\tJSON file with SQL Server \"user\" and \"password\"
+    	--newer\t\t\t\t\tDownload only files that differ from local copies, by date for files without hash
+    	--to <version|date>\t\t\tVersion number or local upload date to roll back to
+    	--dry-run\t\t\t\tShow what upload, download, delete or init would do without changing anything
+    	--mask <mask>\t\t\t\tFile mask to use instead of mode, e.g. \"*.exe;reports/**/*.xls\"
+    	--yes, -y\t\t\t\tDo not ask for confirmation
+    	--dir <path>\t\t\t\tLocal directory instead of current or configured one
+    	--config <path>\t\t\t\tConfig file, {CONFIG_ENV_VAR} environment variable can be used as well
+    	--profile <name>\t\t\tConnection profile from settings.json instead of the default one
+    	--format <text|json|csv>\t\tOutput format of list, status, history and upload/download results
+    	--sort <name|date|size>\t\t\tOrder of list output, newest or largest first for date and size
+    	--verbose, -v\t\t\t\tShow more details\n"
+    );
+    println!(
+        "SQL Server credentials can also be set with {SQL_USER_ENV_VAR} and {SQL_PASSWORD_ENV_VAR}\nenvironment variables. User and password are never combined from different sources.\n"
+    );
+    println!(
+        "With json or csv format only results are written to standard output, progress messages go\nto standard error.\n"
+    );
+    println!(
+        "Without --config settings.json is looked up in the current directory, next to the executable\nand in the user config directory.\n"
+    );
+    println!(
+        "File names are compared ignoring case, unless the mode has \"case_sensitive\": true\nin settings.json, which requires a case sensitive collation of FileName columns.\nUploaded files are stored compressed when the mode has \"compression\": \"zstd\" or \"gzip\",\nolder uncompressed files are still read as they are.\n"
+    );
+    println!(
+        "EXIT CODES:
+    \t0\tsuccess
+    \t1\tinvalid arguments
+    \t2\tconfiguration or credentials file error
+    \t3\tupdate mode not found
+    \t4\tcannot connect to SQL Server
+    \t5\tdatabase query failed
+    \t6\tlocal file or directory error
+    \t7\tdownloaded content does not match stored size or hash
+    \t8\tfile or version not found in database
+    \t9\tinterrupted
+    \t10\tother error
+    \t11\tdatabase schema is outdated, run init\n"
     );
 }
 
@@ -178,7 +383,7 @@ mod tests {
         .unwrap();
 
         assert_eq!(parsed.command, Command::List);
-        assert_eq!(parsed.mode, "xls");
+        assert_eq!(parsed.mode.as_deref(), Some("xls"));
         assert_eq!(parsed.sql_username.as_deref(), Some("sa"));
         assert_eq!(parsed.sql_password.as_deref(), Some("secret"));
     }
@@ -200,14 +405,177 @@ mod tests {
     }
 
     #[test]
-    fn parse_args_requires_both_sql_credentials() {
-        let error = parse_args(&v(&["polymix-update", "upload", "xls", "--sql-user", "sa"]))
-            .unwrap_err();
+    fn parse_args_supports_newer_flag() {
+        let parsed = parse_args(&v(&["polymix-update", "download", "exe", "--newer"])).unwrap();
+
+        assert_eq!(parsed.command, Command::Download);
+        assert!(parsed.only_newer);
+    }
+
+    #[test]
+    fn parse_args_allows_missing_mode() {
+        let parsed = parse_args(&v(&["polymix-update", "download"])).unwrap();
+        assert_eq!(parsed.command, Command::Download);
+        assert_eq!(parsed.mode, None);
+
+        let parsed = parse_args(&v(&["polymix-update", "download", "--newer"])).unwrap();
+        assert_eq!(parsed.mode, None);
+        assert!(parsed.only_newer);
+    }
+
+    #[test]
+    fn parse_args_does_not_take_short_flag_for_mode() {
+        let parsed =
+            parse_args(&v(&["polymix-update", "delete", "-y", "--mask", "*.bak"])).unwrap();
+        assert_eq!(parsed.mode, None);
+        assert_eq!(parsed.mask.as_deref(), Some("*.bak"));
+        assert!(parsed.assume_yes);
+
+        let parsed = parse_args(&v(&["polymix-update", "download", "-v"])).unwrap();
+        assert_eq!(parsed.mode, None);
+        assert!(parsed.verbose);
+    }
+
+    #[test]
+    fn parse_args_supports_dry_run_flag() {
+        let parsed = parse_args(&v(&["polymix-update", "upload", "all", "--dry-run"])).unwrap();
+
+        assert_eq!(parsed.command, Command::Upload);
+        assert!(parsed.dry_run);
+        assert!(!parsed.only_newer);
+    }
+
+    #[test]
+    fn parse_args_supports_mask_instead_of_mode() {
+        let parsed = parse_args(&v(&[
+            "polymix-update",
+            "delete",
+            "--mask",
+            "*.bak",
+            "--yes",
+        ]))
+        .unwrap();
+
+        assert_eq!(parsed.command, Command::Delete);
+        assert_eq!(parsed.mode, None);
+        assert_eq!(parsed.mask.as_deref(), Some("*.bak"));
+        assert!(parsed.assume_yes);
+
+        let error = parse_args(&v(&["polymix-update", "delete", "xls", "--mask", "*.bak"]));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn parse_args_supports_local_dir() {
+        let parsed = parse_args(&v(&["polymix-update", "upload", "exe", "--dir", "bin"])).unwrap();
+
+        assert_eq!(parsed.dir, Some(PathBuf::from("bin")));
+    }
+
+    #[test]
+    fn parse_args_supports_config_path() {
+        let parsed = parse_args(&v(&[
+            "polymix-update",
+            "list",
+            "--config",
+            "c:/polymix/settings.json",
+            "-v",
+        ]))
+        .unwrap();
+
+        assert_eq!(parsed.mode, None);
+        assert_eq!(
+            parsed.config_path,
+            Some(PathBuf::from("c:/polymix/settings.json"))
+        );
+        assert!(parsed.verbose);
+    }
+
+    #[test]
+    fn parse_args_supports_output_format() {
+        let parsed = parse_args(&v(&["polymix-update", "list", "--format", "JSON"])).unwrap();
+        assert_eq!(parsed.format, OutputFormat::Json);
+
+        let parsed = parse_args(&v(&["polymix-update", "list"])).unwrap();
+        assert_eq!(parsed.format, OutputFormat::Text);
+
+        let error = parse_args(&v(&["polymix-update", "list", "--format", "xml"]));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn parse_args_supports_sort_order() {
+        let parsed = parse_args(&v(&["polymix-update", "list", "--sort", "Size"])).unwrap();
+        assert_eq!(parsed.sort, SortOrder::Size);
+
+        let parsed = parse_args(&v(&["polymix-update", "list"])).unwrap();
+        assert_eq!(parsed.sort, SortOrder::Name);
+
+        let error = parse_args(&v(&["polymix-update", "list", "--sort", "owner"]));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn parse_args_supports_profile() {
+        let parsed =
+            parse_args(&v(&["polymix-update", "list", "xls", "--profile", "test"])).unwrap();
+
+        assert_eq!(parsed.profile.as_deref(), Some("test"));
+    }
+
+    #[test]
+    fn parse_args_supports_rollback_target() {
+        let parsed =
+            parse_args(&v(&["polymix-update", "rollback", "calc.exe", "--to", "2"])).unwrap();
+
+        assert_eq!(parsed.command, Command::Rollback);
+        assert_eq!(parsed.mode.as_deref(), Some("calc.exe"));
+        assert_eq!(parsed.rollback_target, RollbackTarget::Version(2));
+
+        let parsed = parse_args(&v(&["polymix-update", "rollback", "calc.exe"])).unwrap();
+        assert_eq!(parsed.rollback_target, RollbackTarget::Previous);
+
+        let error = parse_args(&v(&["polymix-update", "rollback", "a.exe", "--to", "soon"]));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn parse_args_requires_user_for_sql_password() {
+        let error = parse_args(&v(&[
+            "polymix-update",
+            "upload",
+            "xls",
+            "--password",
+            "secret",
+        ]))
+        .unwrap_err();
 
         assert!(
             error
                 .to_string()
-                .contains("Both '--sql-user' and '--sql-password' must be provided together.")
+                .contains("Flag '--sql-password' requires '--sql-user'.")
         );
     }
+
+    #[test]
+    fn parse_args_allows_sql_user_without_password() {
+        let parsed =
+            parse_args(&v(&["polymix-update", "upload", "xls", "--sql-user", "sa"])).unwrap();
+
+        assert_eq!(parsed.sql_username.as_deref(), Some("sa"));
+        assert_eq!(parsed.sql_password, None);
+    }
+
+    #[test]
+    fn parse_args_supports_credentials_file() {
+        let parsed = parse_args(&v(&[
+            "polymix-update",
+            "download",
+            "--credentials-file",
+            "sql.json",
+        ]))
+        .unwrap();
+
+        assert_eq!(parsed.credentials_file, Some(PathBuf::from("sql.json")));
+    }
 }
@@ -0,0 +1,110 @@
+use glob::MatchOptions;
+use std::collections::BTreeMap;
+
+// How file names are compared between the local directory, file masks and the database.
+// Case is ignored by default, as on Windows, so that Calc.EXE and calc.exe are the same file.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct NameMatching {
+    pub case_sensitive: bool,
+}
+
+impl NameMatching {
+    // Names with equal keys refer to the same file
+    pub fn key(&self, name: &str) -> String {
+        if self.case_sensitive {
+            name.to_owned()
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    pub fn match_options(&self, require_literal_separator: bool) -> MatchOptions {
+        MatchOptions {
+            case_sensitive: self.case_sensitive,
+            require_literal_separator,
+            require_literal_leading_dot: false,
+        }
+    }
+
+    // Groups of different names which refer to the same file
+    pub fn duplicates<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Vec<Vec<&'a str>> {
+        let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for name in names {
+            groups.entry(self.key(name)).or_default().push(name);
+        }
+        groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect()
+    }
+
+    // Describes duplicates found among the names, None when there are none
+    pub fn duplicates_message<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Option<String> {
+        let duplicates = self.duplicates(names);
+        if duplicates.is_empty() {
+            return None;
+        }
+        let groups: Vec<String> = duplicates.iter().map(|group| group.join(", ")).collect();
+        Some(format!(
+            "file names differing only in case refer to the same file: {}",
+            groups.join("; ")
+        ))
+    }
+}
+
+// SQL Server collations ending in _CS_.. or _BIN/_BIN2 tell names differing only in case apart
+pub fn is_case_sensitive_collation(collation: &str) -> bool {
+    let collation = collation.to_uppercase();
+    collation.contains("_CS") || collation.ends_with("_BIN") || collation.ends_with("_BIN2")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_ignores_case_unless_case_sensitive() {
+        let matching = NameMatching::default();
+        assert_eq!(matching.key("Reports/Calc.EXE"), "reports/calc.exe");
+
+        let matching = NameMatching {
+            case_sensitive: true,
+        };
+        assert_eq!(matching.key("Reports/Calc.EXE"), "Reports/Calc.EXE");
+    }
+
+    #[test]
+    fn duplicates_groups_case_variants() {
+        let names = ["calc.exe", "prices.xls", "Calc.EXE", "CALC.exe"];
+
+        assert_eq!(
+            NameMatching::default().duplicates(names),
+            [vec!["calc.exe", "Calc.EXE", "CALC.exe"]]
+        );
+        assert!(
+            NameMatching {
+                case_sensitive: true
+            }
+            .duplicates(names)
+            .is_empty()
+        );
+        assert_eq!(
+            NameMatching::default()
+                .duplicates_message(["a.exe", "A.exe"])
+                .unwrap(),
+            "file names differing only in case refer to the same file: a.exe, A.exe"
+        );
+    }
+
+    #[test]
+    fn case_sensitive_collations() {
+        assert!(is_case_sensitive_collation("Latin1_General_CS_AS"));
+        assert!(is_case_sensitive_collation("Latin1_General_100_CS_AS_SC_UTF8"));
+        assert!(is_case_sensitive_collation("Latin1_General_BIN2"));
+        assert!(!is_case_sensitive_collation("SQL_Latin1_General_CP1_CI_AS"));
+        assert!(!is_case_sensitive_collation("Cyrillic_General_CI_AI"));
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter};
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, Display, EnumIter)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<OutputFormat, Self::Err> {
+        OutputFormat::iter()
+            .find(|format| input.eq_ignore_ascii_case(&format.to_string()))
+            .ok_or(())
+    }
+}
+
+// With machine readable output stdout contains only the results,
+// progress messages are written to stderr
+static PROGRESS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn init(format: OutputFormat) {
+    PROGRESS_TO_STDERR.store(format != OutputFormat::Text, Ordering::Relaxed);
+}
+
+pub fn progress_to_stderr() -> bool {
+    PROGRESS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::output::progress_to_stderr() {
+            eprint!($($arg)*)
+        } else {
+            print!($($arg)*)
+        }
+    };
+}
+
+macro_rules! progressln {
+    ($($arg:tt)*) => {
+        if $crate::output::progress_to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+pub(crate) use {progress, progressln};
+
+// Prints records as JSON array or CSV with header, text output is up to the caller
+pub fn print_records<T: Serialize + Default>(format: OutputFormat, records: &[T]) -> Result<()> {
+    match format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(records)?),
+        OutputFormat::Csv => {
+            println!("{}", csv_header(&T::default())?);
+            for record in records {
+                println!("{}", csv_row(record)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Prints a single result object, text output is up to the caller
+pub fn print_summary<T: Serialize + Default>(format: OutputFormat, summary: &T) -> Result<()> {
+    print_records(format, std::slice::from_ref(summary))
+}
+
+fn csv_header<T: Serialize>(record: &T) -> Result<String> {
+    Ok(match serde_json::to_value(record)? {
+        Value::Object(fields) => fields
+            .keys()
+            .map(|key| csv_field(key))
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => String::new(),
+    })
+}
+
+fn csv_row<T: Serialize>(record: &T) -> Result<String> {
+    Ok(match serde_json::to_value(record)? {
+        Value::Object(fields) => fields.values().map(csv_value).collect::<Vec<_>>().join(","),
+        value => csv_value(&value),
+    })
+}
+
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => csv_field(text),
+        Value::Array(values) => csv_field(
+            &values
+                .iter()
+                .map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(";"),
+        ),
+        value => value.to_string(),
+    }
+}
+
+// Quotes the field when it contains separators or quotes
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Default)]
+    struct Record {
+        name: String,
+        tags: Vec<String>,
+        size: Option<i64>,
+    }
+
+    #[test]
+    fn output_format_from_str_ignores_case() {
+        assert_eq!(OutputFormat::from_str("JSON"), Ok(OutputFormat::Json));
+        assert_eq!(OutputFormat::from_str("csv"), Ok(OutputFormat::Csv));
+        assert_eq!(OutputFormat::from_str("xml"), Err(()));
+    }
+
+    #[test]
+    fn csv_keeps_field_order_and_quotes_values() {
+        let record = Record {
+            name: "report, \"final\".xls".to_owned(),
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            size: None,
+        };
+
+        assert_eq!(csv_header(&Record::default()).unwrap(), "name,tags,size");
+        assert_eq!(
+            csv_row(&record).unwrap(),
+            "\"report, \"\"final\"\".xls\",a;b,"
+        );
+    }
+}
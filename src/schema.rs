@@ -0,0 +1,101 @@
+// Database schema changes applied by the init command. Every step checks what already
+// exists, so databases created by hand or by older versions are upgraded in place.
+// Steps are never changed once released, new columns and tables get a new step.
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+pub const SCHEMA_TABLE: &str = "PolyCalcSchema";
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "file table",
+        sql: "IF OBJECT_ID(N'PolyCalcVersion', N'U') IS NULL
+                  CREATE TABLE PolyCalcVersion (
+                      FileName nvarchar(260) NOT NULL PRIMARY KEY,
+                      FileDate datetime NOT NULL,
+                      FileImage varbinary(max) NULL
+                  )",
+    },
+    Migration {
+        version: 2,
+        description: "file hash",
+        sql: "IF COL_LENGTH(N'PolyCalcVersion', N'FileHash') IS NULL
+                  ALTER TABLE PolyCalcVersion ADD FileHash varchar(64) NULL",
+    },
+    Migration {
+        version: 3,
+        description: "version history",
+        sql: "IF OBJECT_ID(N'PolyCalcVersionHistory', N'U') IS NULL
+                  CREATE TABLE PolyCalcVersionHistory (
+                      FileName nvarchar(260) NOT NULL,
+                      Version int NOT NULL,
+                      FileDate datetime NOT NULL,
+                      FileHash varchar(64) NULL,
+                      FileImage varbinary(max) NULL,
+                      UploadedBy nvarchar(128) NULL,
+                      UploadDate datetime NULL,
+                      IsCurrent bit NOT NULL,
+                      CONSTRAINT PK_PolyCalcVersionHistory PRIMARY KEY (FileName, Version)
+                  )",
+    },
+    Migration {
+        version: 4,
+        description: "upload metadata",
+        sql: "IF COL_LENGTH(N'PolyCalcVersion', N'FileSize') IS NULL
+                  ALTER TABLE PolyCalcVersion ADD FileSize bigint NULL;
+              IF COL_LENGTH(N'PolyCalcVersion', N'UploadedBy') IS NULL
+                  ALTER TABLE PolyCalcVersion ADD UploadedBy nvarchar(128) NULL;
+              IF COL_LENGTH(N'PolyCalcVersion', N'UploadHost') IS NULL
+                  ALTER TABLE PolyCalcVersion ADD UploadHost nvarchar(128) NULL;
+              IF COL_LENGTH(N'PolyCalcVersion', N'UploadDate') IS NULL
+                  ALTER TABLE PolyCalcVersion ADD UploadDate datetime NULL;
+              IF COL_LENGTH(N'PolyCalcVersionHistory', N'FileSize') IS NULL
+                  ALTER TABLE PolyCalcVersionHistory ADD FileSize bigint NULL;
+              IF COL_LENGTH(N'PolyCalcVersionHistory', N'UploadHost') IS NULL
+                  ALTER TABLE PolyCalcVersionHistory ADD UploadHost nvarchar(128) NULL",
+    },
+    Migration {
+        version: 5,
+        description: "image compression",
+        sql: "IF COL_LENGTH(N'PolyCalcVersion', N'Compression') IS NULL
+                  ALTER TABLE PolyCalcVersion ADD Compression varchar(16) NULL;
+              IF COL_LENGTH(N'PolyCalcVersionHistory', N'Compression') IS NULL
+                  ALTER TABLE PolyCalcVersionHistory ADD Compression varchar(16) NULL",
+    },
+];
+
+// Version of a database with all migrations applied
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+pub fn pending_migrations(current_version: i32) -> &'static [Migration] {
+    let applied = MIGRATIONS
+        .iter()
+        .take_while(|migration| migration.version <= current_version)
+        .count();
+    &MIGRATIONS[applied..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_versions_are_consecutive() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i32 + 1);
+        }
+    }
+
+    #[test]
+    fn pending_migrations_skips_applied_ones() {
+        assert_eq!(pending_migrations(0).len(), MIGRATIONS.len());
+        assert_eq!(pending_migrations(1)[0].version, 2);
+        assert!(pending_migrations(latest_version()).is_empty());
+    }
+}
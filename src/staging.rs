@@ -0,0 +1,224 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+const STAGING_DIR: &str = ".polymix-update.staging";
+const BACKUP_DIR: &str = ".polymix-update.backup";
+
+// Staging and backup directories must not be uploaded
+pub fn is_reserved(file_name: &str) -> bool {
+    let first = file_name.split('/').next().unwrap_or_default();
+    first == STAGING_DIR || first == BACKUP_DIR
+}
+
+// Downloaded files are written to a staging directory first and moved into place
+// only after all of them arrived. Replaced files are moved to a backup directory,
+// which is kept until the next download and used to restore them when installation fails.
+pub struct Staging {
+    target_dir: PathBuf,
+    staging_dir: PathBuf,
+    backup_dir: PathBuf,
+    files: Vec<String>,
+}
+
+// How far installation of a file got, restore undoes only these steps
+#[derive(Debug, Default)]
+struct InstallState {
+    backed_up: bool,
+    installed: bool,
+}
+
+impl Staging {
+    pub fn new(target_dir: &Path) -> Result<Staging> {
+        let staging_dir = target_dir.join(STAGING_DIR);
+        // leftovers of an interrupted download
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        fs::create_dir_all(&staging_dir).with_context(|| {
+            format!(
+                "Failed to create staging directory {}",
+                staging_dir.display()
+            )
+        })?;
+
+        Ok(Staging {
+            target_dir: target_dir.to_owned(),
+            staging_dir,
+            backup_dir: target_dir.join(BACKUP_DIR),
+            files: vec![],
+        })
+    }
+
+    // Creates the file in the staging directory, returns it for writing with its staged path
+    pub fn create(&mut self, file_name: &str) -> Result<(File, PathBuf)> {
+        let staged_path = self.staging_dir.join(file_name);
+        if let Some(parent) = staged_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&staged_path)?;
+        self.files.push(file_name.to_owned());
+        Ok((file, staged_path))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Moves all staged files into the target directory, restores replaced files on failure
+    pub fn install(&self) -> Result<()> {
+        if self.backup_dir.exists() {
+            fs::remove_dir_all(&self.backup_dir)?;
+        }
+        fs::create_dir_all(&self.backup_dir)?;
+
+        let mut states: Vec<(&str, InstallState)> = vec![];
+        for file_name in &self.files {
+            let mut state = InstallState::default();
+            let result = self.install_file(file_name, &mut state);
+            // the failed file may be half way installed as well
+            states.push((file_name, state));
+            if let Err(error) = result {
+                let restore_result = self.restore(&states);
+                let error = error.context(format!("Failed to install {file_name}"));
+                return match restore_result {
+                    Ok(()) => Err(error.context("Previous files were restored")),
+                    Err(restore_error) => Err(error.context(format!(
+                        "Failed to restore previous files from {}: {restore_error}",
+                        self.backup_dir.display()
+                    ))),
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    fn install_file(&self, file_name: &str, state: &mut InstallState) -> Result<()> {
+        let target_path = self.target_dir.join(file_name);
+        if target_path.exists() {
+            let backup_path = self.backup_dir.join(file_name);
+            if let Some(parent) = backup_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&target_path, backup_path)?;
+            state.backed_up = true;
+        }
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(self.staging_dir.join(file_name), target_path)?;
+        state.installed = true;
+        Ok(())
+    }
+
+    // Files which were not moved to backup are still the user's originals and are kept
+    fn restore(&self, states: &[(&str, InstallState)]) -> Result<()> {
+        for (file_name, state) in states.iter().rev() {
+            let target_path = self.target_dir.join(file_name);
+            if state.installed {
+                fs::remove_file(&target_path)?;
+            }
+            if state.backed_up {
+                fs::rename(self.backup_dir.join(file_name), target_path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.staging_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn stage(staging: &mut Staging, file_name: &str, content: &[u8]) -> PathBuf {
+        let (mut file, staged_path) = staging.create(file_name).unwrap();
+        file.write_all(content).unwrap();
+        staged_path
+    }
+
+    #[test]
+    fn is_reserved_matches_staging_and_backup_directories() {
+        assert!(is_reserved(".polymix-update.backup/calc.exe"));
+        assert!(is_reserved(".polymix-update.staging/reports/monthly.xls"));
+        assert!(!is_reserved("reports/monthly.xls"));
+    }
+
+    #[test]
+    fn install_replaces_files_and_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("calc.exe"), b"old").unwrap();
+
+        let mut staging = Staging::new(dir.path()).unwrap();
+        stage(&mut staging, "calc.exe", b"new");
+        stage(&mut staging, "reports/monthly.xls", b"report");
+        staging.install().unwrap();
+        drop(staging);
+
+        assert_eq!(fs::read(dir.path().join("calc.exe")).unwrap(), b"new");
+        assert_eq!(
+            fs::read(dir.path().join("reports").join("monthly.xls")).unwrap(),
+            b"report"
+        );
+        assert_eq!(
+            fs::read(dir.path().join(BACKUP_DIR).join("calc.exe")).unwrap(),
+            b"old"
+        );
+        assert!(!dir.path().join(STAGING_DIR).exists());
+    }
+
+    #[test]
+    fn install_restores_files_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("calc.exe"), b"old").unwrap();
+        fs::write(dir.path().join("report.xls"), b"old report").unwrap();
+
+        let mut staging = Staging::new(dir.path()).unwrap();
+        stage(&mut staging, "calc.exe", b"new");
+        stage(&mut staging, "new.xls", b"new file");
+        let staged_path = stage(&mut staging, "report.xls", b"new report");
+        fs::remove_file(staged_path).unwrap();
+
+        assert!(staging.install().is_err());
+
+        assert_eq!(fs::read(dir.path().join("calc.exe")).unwrap(), b"old");
+        assert_eq!(
+            fs::read(dir.path().join("report.xls")).unwrap(),
+            b"old report"
+        );
+        assert!(!dir.path().join("new.xls").exists());
+    }
+
+    #[test]
+    fn restore_keeps_files_which_were_not_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("calc.exe"), b"old").unwrap();
+        fs::write(dir.path().join("new.xls"), b"new file").unwrap();
+
+        let staging = Staging::new(dir.path()).unwrap();
+        let states = [
+            (
+                "new.xls",
+                InstallState {
+                    backed_up: false,
+                    installed: true,
+                },
+            ),
+            // failed before it was moved to backup
+            ("calc.exe", InstallState::default()),
+        ];
+        staging.restore(&states).unwrap();
+
+        assert_eq!(fs::read(dir.path().join("calc.exe")).unwrap(), b"old");
+        assert!(!dir.path().join("new.xls").exists());
+    }
+}
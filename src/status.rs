@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use strum_macros::{Display, EnumIter};
+
+use crate::db::DBFile;
+
+// How a local file relates to the file stored in database
+#[derive(Debug, PartialEq, Clone, Copy, Display, EnumIter)]
+pub enum FileStatus {
+    #[strum(serialize = "new locally")]
+    NewLocal,
+    #[strum(serialize = "newer locally")]
+    NewerLocal,
+    #[strum(serialize = "newer in database")]
+    NewerInDb,
+    #[strum(serialize = "identical")]
+    Identical,
+    #[strum(serialize = "only in database")]
+    OnlyInDb,
+}
+
+pub struct LocalFile {
+    pub date: DateTime<Utc>,
+    pub size: u64,
+    // only calculated when database hash is available to compare with
+    pub hash: Option<String>,
+}
+
+impl LocalFile {
+    // Hashing is only worth it when the database has a hash and the sizes do not already differ
+    pub fn needs_hash(size: u64, db_file: Option<&DBFile>) -> bool {
+        db_file.is_some_and(|db_file| {
+            db_file.hash.is_some() && db_file.size.is_none_or(|db_size| db_size == size as i64)
+        })
+    }
+}
+
+// Content is compared by size and hash when they are known, otherwise by dates.
+// When the database file has a hash and the content differs, the database copy wins
+// regardless of dates, a rollback makes an older build current again.
+// Dates are compared with one second precision because SQL Server datetime rounds milliseconds.
+pub fn classify(local_file: Option<&LocalFile>, db_file: Option<&DBFile>) -> FileStatus {
+    let (local_file, db_file) = match (local_file, db_file) {
+        (Some(local_file), Some(db_file)) => (local_file, db_file),
+        (Some(_), None) => return FileStatus::NewLocal,
+        _ => return FileStatus::OnlyInDb,
+    };
+
+    let same_size = db_file
+        .size
+        .is_none_or(|db_size| db_size == local_file.size as i64);
+    let same_hash = match (&local_file.hash, &db_file.hash) {
+        (Some(local_hash), Some(db_hash)) => Some(local_hash.eq_ignore_ascii_case(db_hash)),
+        _ => None,
+    };
+
+    if same_size && same_hash == Some(true) {
+        return FileStatus::Identical;
+    }
+    if db_file.hash.is_some() && (!same_size || same_hash == Some(false)) {
+        return FileStatus::NewerInDb;
+    }
+
+    let local_date = local_file.date.timestamp();
+    let db_date = db_file.date.and_utc().timestamp();
+
+    if local_date > db_date {
+        FileStatus::NewerLocal
+    } else if local_date < db_date {
+        FileStatus::NewerInDb
+    } else if same_size && same_hash.is_none() {
+        FileStatus::Identical
+    } else {
+        // same date but different content, upload would replace the database file
+        FileStatus::NewerLocal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn date(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %T").unwrap()
+    }
+
+    fn db_file(date_str: &str, size: Option<i64>, hash: Option<&str>) -> DBFile {
+        DBFile {
+            name: "calc.exe".to_owned(),
+            date: date(date_str),
+            size,
+            hash: hash.map(ToOwned::to_owned),
+            uploaded_by: None,
+            upload_host: None,
+            upload_date: None,
+        }
+    }
+
+    fn local_file(date_str: &str, size: u64, hash: Option<&str>) -> LocalFile {
+        LocalFile {
+            date: date(date_str).and_utc(),
+            size,
+            hash: hash.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn classify_missing_files() {
+        let local = local_file("2026-01-01 10:00:00", 10, None);
+        let db = db_file("2026-01-01 10:00:00", Some(10), None);
+
+        assert_eq!(classify(Some(&local), None), FileStatus::NewLocal);
+        assert_eq!(classify(None, Some(&db)), FileStatus::OnlyInDb);
+    }
+
+    #[test]
+    fn classify_by_hash_ignores_dates() {
+        let local = local_file("2026-05-01 10:00:00", 10, Some("ABC"));
+        let db = db_file("2026-01-01 10:00:00", Some(10), Some("abc"));
+
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::Identical);
+    }
+
+    #[test]
+    fn classify_by_dates() {
+        let db = db_file("2026-01-01 10:00:00", Some(10), None);
+
+        let local = local_file("2026-01-01 10:00:01", 10, None);
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::NewerLocal);
+
+        let local = local_file("2025-12-31 10:00:00", 12, None);
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::NewerInDb);
+    }
+
+    #[test]
+    fn classify_prefers_database_content_after_rollback() {
+        // local copy is the newer build which was rolled back in database
+        let db = db_file("2026-01-01 10:00:00", Some(10), Some("abc"));
+
+        let local = local_file("2026-02-01 10:00:00", 10, Some("def"));
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::NewerInDb);
+
+        let local = local_file("2026-02-01 10:00:00", 12, None);
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::NewerInDb);
+    }
+
+    #[test]
+    fn classify_same_date_without_hash() {
+        let db = db_file("2026-01-01 10:00:00", Some(10), None);
+
+        let local = local_file("2026-01-01 10:00:00", 10, None);
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::Identical);
+
+        let local = local_file("2026-01-01 10:00:00", 11, None);
+        assert_eq!(classify(Some(&local), Some(&db)), FileStatus::NewerLocal);
+    }
+
+    #[test]
+    fn needs_hash_only_when_it_can_decide() {
+        let db = db_file("2026-01-01 10:00:00", Some(10), Some("abc"));
+        assert!(LocalFile::needs_hash(10, Some(&db)));
+        assert!(!LocalFile::needs_hash(11, Some(&db)));
+        assert!(!LocalFile::needs_hash(10, None));
+
+        let db = db_file("2026-01-01 10:00:00", None, None);
+        assert!(!LocalFile::needs_hash(10, Some(&db)));
+    }
+}
@@ -1,36 +1,120 @@
-use anyhow::Result;
-use chrono::{DateTime, Local, NaiveDateTime, Utc};
-use glob::{MatchOptions, Pattern, glob_with};
-use std::{fs, path::PathBuf, process, time::SystemTime};
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, NaiveDateTime, SecondsFormat, Utc};
+use filetime::FileTime;
+use glob::{Pattern, glob_with};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+use strum::IntoEnumIterator;
 
 use crate::{
-    command::Command,
-    config::Config,
-    db::{DB, DBFile},
+    command::{Command, RollbackTarget, SortOrder},
+    compression::Compression,
+    config::{Config, UpdateMode},
+    db::{DB, DBFile, DBFileVersion, FileUpload, UploadSource},
+    error::UpdateError,
+    hashing::{self, Hashing},
+    names::{self, NameMatching},
+    output::{self, OutputFormat, progress, progressln},
+    schema,
+    staging::{self, Staging},
+    status::{self, FileStatus, LocalFile},
 };
 
 pub struct Updater<'a> {
     config: &'a Config,
     command: Command,
-    update_mode_name: String,
-    sql_username: Option<String>,
-    sql_password: Option<String>,
+    update_mode_name: Option<String>,
+    options: Options,
+}
+
+// Command line flags changing how commands are executed
+#[derive(Debug, Default)]
+pub struct Options {
+    pub sql_username: Option<String>,
+    pub sql_password: Option<String>,
+    pub only_newer: bool,
+    pub rollback_target: RollbackTarget,
+    pub dry_run: bool,
+    // file mask used instead of update mode
+    pub mask: Option<String>,
+    // do not ask for confirmation
+    pub assume_yes: bool,
+    // local directory used instead of the configured one
+    pub dir: Option<PathBuf>,
+    // connection profile used instead of the default one
+    pub profile: Option<String>,
+    pub format: OutputFormat,
+    // order of list output
+    pub sort: SortOrder,
+}
+
+// Results of upload and download, also printed as machine readable summary
+#[derive(Default, Serialize)]
+struct UploadStats {
+    dry_run: bool,
+    inserted: usize,
+    updated: usize,
+    skipped: usize,
+    bytes: u64,
+}
+
+#[derive(Default, Serialize)]
+struct DownloadStats {
+    dry_run: bool,
+    downloaded: usize,
+    skipped: usize,
+    bytes: u64,
+    rejected: Vec<String>,
+}
+
+// Machine readable records of list, status and history commands
+#[derive(Default, Serialize)]
+struct FileRecord {
+    name: String,
+    date: String,
+    size: Option<i64>,
+    hash: Option<String>,
+    uploaded_by: Option<String>,
+    upload_host: Option<String>,
+    upload_date: Option<String>,
+}
+
+#[derive(Default, Serialize)]
+struct StatusRecord {
+    name: String,
+    status: String,
+}
+
+#[derive(Default, Serialize)]
+struct VersionRecord {
+    version: i32,
+    current: bool,
+    date: String,
+    hash: Option<String>,
+    uploaded_by: Option<String>,
+    upload_date: Option<String>,
 }
 
 impl Updater<'_> {
     pub fn new<'a>(
         config: &'a Config,
         command: Command,
-        update_mode_name: &str,
-        sql_username: Option<String>,
-        sql_password: Option<String>,
+        update_mode_name: Option<String>,
+        options: Options,
     ) -> Updater<'a> {
         Updater {
             config,
             command,
-            update_mode_name: update_mode_name.to_owned(),
-            sql_username,
-            sql_password,
+            update_mode_name,
+            options,
         }
     }
 
@@ -39,6 +123,11 @@ impl Updater<'_> {
             Command::Upload => self.upload().await?,
             Command::Download => self.download().await?,
             Command::List => self.list().await?,
+            Command::History => self.history().await?,
+            Command::Rollback => self.rollback().await?,
+            Command::Status => self.status().await?,
+            Command::Delete => self.delete().await?,
+            Command::Init => self.init().await?,
         }
         Ok(())
     }
@@ -48,14 +137,8 @@ impl Updater<'_> {
         F: FnOnce(&'a Self, String) -> Fut,
         Fut: std::future::Future<Output = Result<()>> + 'a,
     {
-        match self.get_file_mask() {
-            Some(pattern) => process_fn(self, pattern).await?,
-            None => {
-                // TODO: we do not handle default file masks at the moment
-                process::exit(1)
-            }
-        }
-        Ok(())
+        let pattern = self.get_file_mask()?;
+        process_fn(self, pattern).await
     }
 
     async fn upload(&self) -> Result<()> {
@@ -70,34 +153,363 @@ impl Updater<'_> {
         self.process_files(Self::list_files).await
     }
 
+    async fn status(&self) -> Result<()> {
+        self.process_files(Self::status_files).await
+    }
+
+    async fn delete(&self) -> Result<()> {
+        self.process_files(Self::delete_files).await
+    }
+
+    async fn history(&self) -> Result<()> {
+        let file_name = self.get_file_name()?;
+        let mut client = self.connect().await?;
+
+        let versions = client.get_file_versions(file_name).await?;
+
+        if self.options.format != OutputFormat::Text {
+            let records: Vec<VersionRecord> = versions
+                .iter()
+                .map(|version| VersionRecord {
+                    version: version.version,
+                    current: version.is_current,
+                    date: Self::format_iso_date_time(version.date),
+                    hash: version.hash.clone(),
+                    uploaded_by: version.uploaded_by.clone(),
+                    upload_date: version.upload_date.map(Self::format_iso_date_time),
+                })
+                .collect();
+            return output::print_records(self.options.format, &records);
+        }
+
+        progressln!();
+
+        // upload date comes first in local time, in the same form as rollback --to accepts it
+        for version in &versions {
+            progressln!(
+                "{}{}\t{}\t{}\t{}\t{}",
+                if version.is_current { "*" } else { " " },
+                version.version,
+                Self::format_local_date_time(version.upload_date.unwrap_or(version.date)),
+                version.uploaded_by.as_deref().unwrap_or("-"),
+                Self::format_local_date_time(version.date),
+                version.hash.as_deref().unwrap_or("-"),
+            );
+        }
+
+        progressln!("\n{} version(s) of {}", versions.len(), file_name);
+
+        Ok(())
+    }
+
+    // Creates the tables on an empty database and applies missing schema steps on older ones
+    async fn init(&self) -> Result<()> {
+        let mut client = self.connect().await?;
+
+        let current_version = client.get_schema_version().await?;
+        let migrations = schema::pending_migrations(current_version);
+        if migrations.is_empty() {
+            progressln!("Database schema is up to date (version {current_version})");
+            return Ok(());
+        }
+
+        if self.options.dry_run {
+            for migration in migrations {
+                progressln!(
+                    "Would apply schema version {} ({})",
+                    migration.version,
+                    migration.description
+                );
+            }
+            progressln!("\nDry run, database was not changed");
+            return Ok(());
+        }
+
+        for migration in migrations {
+            client.apply_migration(migration).await?;
+        }
+        progressln!(
+            "\nDatabase schema upgraded from version {current_version} to {}",
+            schema::latest_version()
+        );
+
+        Ok(())
+    }
+
+    async fn rollback(&self) -> Result<()> {
+        let file_name = self.get_file_name()?;
+        let mut client = self.connect().await?;
+
+        let versions = client.get_file_versions(file_name).await?;
+        let version = Self::select_rollback_version(&versions, &self.options.rollback_target)?;
+
+        progressln!("Rolling back {file_name}:");
+        client.set_current_version(file_name, version).await?;
+
+        Ok(())
+    }
+
+    fn select_rollback_version(versions: &[DBFileVersion], target: &RollbackTarget) -> Result<i32> {
+        let current = versions
+            .iter()
+            .find(|version| version.is_current)
+            .ok_or_else(|| UpdateError::NotFound("File has no stored versions".to_owned()))?;
+
+        let selected = match target {
+            RollbackTarget::Previous => versions
+                .iter()
+                .filter(|version| version.version < current.version)
+                .max_by_key(|version| version.version),
+            RollbackTarget::Version(number) => {
+                versions.iter().find(|version| version.version == *number)
+            }
+            RollbackTarget::Date(date) => versions
+                .iter()
+                .filter(|version| version.upload_date.unwrap_or(version.date) <= *date)
+                .max_by_key(|version| version.version),
+        }
+        .ok_or_else(|| UpdateError::NotFound("Version to roll back to not found".to_owned()))?;
+
+        if selected.is_current {
+            return Err(UpdateError::InvalidArguments(format!(
+                "Version {} is already current",
+                selected.version
+            ))
+            .into());
+        }
+
+        Ok(selected.version)
+    }
+
     async fn download_files(&self, pattern_str: String) -> Result<()> {
         let mut client = self.connect().await?;
 
-        println!("Downloading files:");
+        if self.options.dry_run {
+            progressln!("Files to download (dry run):");
+        } else {
+            progressln!("Downloading files:");
+        }
+
+        let name_matching = self.get_name_matching()?;
+        let db_files = Self::get_matched_db_files(&mut client, &pattern_str, name_matching).await?;
+        // such files would overwrite each other in the local directory
+        if let Some(message) =
+            name_matching.duplicates_message(db_files.iter().map(|db_file| db_file.name.as_str()))
+        {
+            return Err(UpdateError::Database(format!("Cannot download, {message}")).into());
+        }
 
-        let db_files = Self::get_matched_db_files(&mut client, &pattern_str).await?;
+        let local_dir = self.get_local_dir()?;
+        let mut staging = if self.options.dry_run {
+            None
+        } else {
+            Some(Staging::new(&local_dir)?)
+        };
+        let mut stats = DownloadStats {
+            dry_run: self.options.dry_run,
+            ..Default::default()
+        };
 
         for db_file in db_files {
-            print!("{}...", db_file.name);
-            let content = client.get_db_file_content(&db_file.name).await?;
-            match content {
-                Some(content) => {
-                    fs::write(db_file.name, content)?;
-                    println!("OK");
+            progress!("{}...", db_file.name);
+            let file_name = match Self::safe_file_name(&db_file.name) {
+                Ok(file_name) => file_name,
+                Err(error) => {
+                    progressln!("Rejected, {error}");
+                    stats.rejected.push(db_file.name);
+                    continue;
                 }
-                None => println!("Zero length, skipped"),
+            };
+            if self.options.only_newer
+                && self.is_local_copy_current(&local_dir.join(&file_name), &db_file)?
+            {
+                progressln!("Up to date, skipped");
+                stats.skipped += 1;
+                continue;
             }
+            let Some(staging) = staging.as_mut() else {
+                match db_file.size {
+                    Some(size) => {
+                        progressln!("Would download {size} bytes");
+                        stats.downloaded += 1;
+                        stats.bytes += size as u64;
+                    }
+                    None => {
+                        progressln!("Zero length, would skip");
+                        stats.skipped += 1;
+                    }
+                }
+                continue;
+            };
+            // size is null when the image is
+            if db_file.size.is_none() {
+                progressln!("Zero length, skipped");
+                stats.skipped += 1;
+                continue;
+            }
+            let (file, staged_path) = staging.create(&file_name)?;
+            let mut writer = Hashing::new(file);
+            client.read_file_content(&db_file.name, &mut writer).await?;
+            let (file, size, hash) = writer.finish();
+            // closed before its modification time is set
+            drop(file);
+            Self::verify_content(&db_file, size, &hash)?;
+            Self::set_modified_to_db_date(&staged_path, &db_file)?;
+            stats.bytes += size;
+            stats.downloaded += 1;
+            progressln!("OK");
+        }
+
+        if let Some(staging) = staging
+            && !staging.is_empty()
+        {
+            progress!("Installing downloaded files...");
+            staging.install()?;
+            progressln!("OK");
+        }
+
+        if self.options.format != OutputFormat::Text {
+            return output::print_summary(self.options.format, &stats);
+        }
+
+        progressln!(
+            "\n{} file(s) {}, {} skipped, {} bytes",
+            stats.downloaded,
+            if stats.dry_run {
+                "would be downloaded"
+            } else {
+                "downloaded"
+            },
+            stats.skipped,
+            stats.bytes
+        );
+
+        if !stats.rejected.is_empty() {
+            progressln!(
+                "{} file(s) rejected because of unsafe names: {}",
+                stats.rejected.len(),
+                stats.rejected.join(", ")
+            );
         }
 
         Ok(())
     }
 
-    fn get_local_files(pattern_str: &str) -> Result<Vec<PathBuf>> {
-        let options = MatchOptions {
-            case_sensitive: false,
-            require_literal_separator: false,
-            require_literal_leading_dot: false,
+    // Normalises the stored file name into a path relative to the target directory.
+    // Names of files written outside of it, absolute or with drive letters are rejected.
+    fn safe_file_name(db_name: &str) -> Result<String> {
+        if db_name.starts_with(['/', '\\']) {
+            return Err(anyhow!("absolute path is not allowed"));
+        }
+
+        let mut parts: Vec<&str> = vec![];
+        for part in db_name.split(['/', '\\']) {
+            match part {
+                "" | "." => {}
+                ".." => return Err(anyhow!("parent directory is not allowed")),
+                _ if part.contains(':') => {
+                    return Err(anyhow!("drive letters and streams are not allowed"));
+                }
+                _ => parts.push(part),
+            }
+        }
+
+        let file_name = parts.join("/");
+        if file_name.is_empty() {
+            return Err(anyhow!("empty file name"));
+        }
+        if staging::is_reserved(&file_name) {
+            return Err(anyhow!("reserved directory name"));
+        }
+
+        Ok(file_name)
+    }
+
+    // Local copy does not need to be downloaded when it has the same content as the DB file
+    // or was modified at the same time or later
+    fn is_local_copy_current(&self, path: &Path, db_file: &DBFile) -> Result<bool> {
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        let local_file = Self::read_local_file(path, Some(db_file))?;
+
+        match status::classify(Some(&local_file), Some(db_file)) {
+            FileStatus::Identical => {
+                // keep the dates in sync, so that the date comparison is stable as well
+                if !self.options.dry_run {
+                    Self::set_modified_to_db_date(path, db_file)?;
+                }
+                Ok(true)
+            }
+            FileStatus::NewerLocal => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    fn read_local_file(path: &Path, db_file: Option<&DBFile>) -> Result<LocalFile> {
+        let metadata = fs::metadata(path)?;
+        let hash = if LocalFile::needs_hash(metadata.len(), db_file) {
+            Some(hashing::hash_reader(File::open(path)?)?.1)
+        } else {
+            None
         };
+
+        Ok(LocalFile {
+            date: metadata.modified()?.into(),
+            size: metadata.len(),
+            hash,
+        })
+    }
+
+    // Checks that downloaded content matches the size and hash stored with the file
+    fn verify_content(db_file: &DBFile, size: u64, hash: &str) -> Result<()> {
+        if db_file.size.is_some_and(|db_size| db_size != size as i64) {
+            return Err(UpdateError::Verification(format!(
+                "Downloaded size of {} does not match stored size",
+                db_file.name
+            ))
+            .into());
+        }
+        if db_file
+            .hash
+            .as_ref()
+            .is_some_and(|db_hash| !db_hash.eq_ignore_ascii_case(hash))
+        {
+            return Err(UpdateError::Verification(format!(
+                "Downloaded content of {} does not match stored hash",
+                db_file.name
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    // FileDate is stored in UTC
+    fn set_modified_to_db_date(path: &Path, db_file: &DBFile) -> Result<()> {
+        let db_date = db_file.date.and_utc();
+        let file_time =
+            FileTime::from_unix_time(db_date.timestamp(), db_date.timestamp_subsec_nanos());
+        filetime::set_file_mtime(path, file_time)?;
+        Ok(())
+    }
+
+    // Returns matching files with their names relative to the local directory,
+    // "**" in the mask matches files in subdirectories
+    fn get_local_files(
+        local_dir: &Path,
+        pattern_str: &str,
+        name_matching: NameMatching,
+    ) -> Result<Vec<(String, PathBuf)>> {
+        if !local_dir.is_dir() {
+            return Err(UpdateError::LocalFile(format!(
+                "Directory {} not found",
+                local_dir.display()
+            ))
+            .into());
+        }
+
+        let options = name_matching.match_options(false);
         // let pattern_paths = pattern_str
         //     .split(';')
         //     .map(|pattern| glob_with(pattern, options))
@@ -109,43 +521,217 @@ impl Updater<'_> {
         //     .flatten()
         //     .collect::<Result<Vec<_>, _>>()?;
 
-        let mut paths: Vec<PathBuf> = vec![];
+        let mut files: Vec<(String, PathBuf)> = vec![];
+        let local_dir_pattern = Pattern::escape(&local_dir.to_string_lossy());
         for pattern in pattern_str.split(';') {
-            for entry in glob_with(pattern, options)? {
-                let path = entry?;
-                if path.file_name().is_some() {
-                    paths.push(path);
+            let entries = glob_with(&format!("{local_dir_pattern}/{pattern}"), options)
+                .with_context(|| {
+                    UpdateError::InvalidArguments(format!("Invalid file mask '{pattern}'"))
+                })?;
+            for entry in entries {
+                let path = entry.map_err(|error| {
+                    UpdateError::LocalFile(format!(
+                        "Failed to read {}: {}",
+                        error.path().display(),
+                        error.error()
+                    ))
+                })?;
+                if !path.is_file() {
+                    continue;
+                }
+                let file_name = Self::local_file_name(&Self::relative_path(&path, local_dir)?)?;
+                if !staging::is_reserved(&file_name) {
+                    files.push((file_name, path));
                 }
             }
         }
 
-        paths.dedup();
+        // the same file can match several patterns
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        files.dedup_by(|(a, _), (b, _)| a == b);
+
+        Ok(files)
+    }
+
+    // Path of the file within the directory. glob drops "." components of the pattern,
+    // so they are skipped on both sides.
+    fn relative_path(path: &Path, dir: &Path) -> Result<PathBuf> {
+        let mut components = path.components().filter(|c| *c != Component::CurDir);
+        for dir_component in dir.components().filter(|c| *c != Component::CurDir) {
+            if components.next() != Some(dir_component) {
+                return Err(UpdateError::LocalFile(format!(
+                    "File {} is outside of the local directory {}",
+                    path.display(),
+                    dir.display()
+                ))
+                .into());
+            }
+        }
+        Ok(components.collect())
+    }
 
-        Ok(paths)
+    // File name stored in database is the relative path with "/" separators
+    fn local_file_name(path: &Path) -> Result<String> {
+        let mut parts: Vec<String> = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().into()),
+                Component::CurDir => {}
+                _ => {
+                    return Err(UpdateError::LocalFile(format!(
+                        "File {} is outside of the local directory",
+                        path.display()
+                    ))
+                    .into());
+                }
+            }
+        }
+        Ok(parts.join("/"))
     }
 
     async fn upload_files(&self, pattern_str: String) -> Result<()> {
-        let local_files = Self::get_local_files(&pattern_str)?;
+        let name_matching = self.get_name_matching()?;
+        let local_files =
+            Self::get_local_files(&self.get_local_dir()?, &pattern_str, name_matching)?;
         if local_files.is_empty() {
+            // scripts still get a summary with zero counts
+            if self.options.format != OutputFormat::Text {
+                let stats = UploadStats {
+                    dry_run: self.options.dry_run,
+                    ..Default::default()
+                };
+                return output::print_summary(self.options.format, &stats);
+            }
             return Ok(());
         }
+        if let Some(message) =
+            name_matching.duplicates_message(local_files.iter().map(|(name, _)| name.as_str()))
+        {
+            return Err(UpdateError::LocalFile(format!("Cannot upload, {message}")).into());
+        }
 
         let mut client = self.connect().await?;
 
-        let db_files: Vec<String> = client
-            .get_db_files()
-            .await?
+        if self.options.dry_run {
+            progressln!("Files to upload (dry run):");
+        }
+
+        let db_files = client
+            .get_db_files(&Self::like_patterns(&pattern_str))
+            .await?;
+        let db_files = Self::get_db_files_to_update(db_files, &local_files, name_matching)?;
+
+        let stats = if self.options.dry_run {
+            self.upload_local_files(&mut client, local_files, &db_files)
+                .await?
+        } else {
+            // All files are uploaded in one transaction, so that the database never contains
+            // a mix of new and old files
+            client.begin_transaction().await?;
+            let result = tokio::select! {
+                result = self.upload_local_files(&mut client, local_files, &db_files) => result,
+                _ = tokio::signal::ctrl_c() => {
+                    // The connection may be in the middle of a request, so it is just closed,
+                    // SQL Server rolls back the open transaction of a closed session
+                    progressln!();
+                    return Err(UpdateError::Interrupted(
+                        "Upload interrupted, no changes were saved".to_owned(),
+                    )
+                    .into());
+                }
+            };
+            match result {
+                Ok(stats) => {
+                    client.commit_transaction().await?;
+                    stats
+                }
+                Err(error) => {
+                    // keep the original error, server rolls back anyway when connection closes
+                    if let Err(rollback_error) = client.rollback_transaction().await {
+                        progressln!("\nRollback failed: {rollback_error}");
+                    }
+                    progressln!("\nUpload failed, no changes were saved");
+                    return Err(error);
+                }
+            }
+        };
+
+        if self.options.format != OutputFormat::Text {
+            return output::print_summary(self.options.format, &stats);
+        }
+
+        if self.options.dry_run {
+            progressln!(
+                "\n{} file(s) would be inserted, {} updated, {} skipped as unchanged, {} bytes to upload",
+                stats.inserted,
+                stats.updated,
+                stats.skipped,
+                stats.bytes
+            );
+        } else {
+            progressln!(
+                "\n{} file(s) inserted, {} updated, {} skipped as unchanged, {} bytes uploaded",
+                stats.inserted,
+                stats.updated,
+                stats.skipped,
+                stats.bytes
+            );
+        }
+
+        Ok(())
+    }
+
+    // Database files updated by the local files, by key. They keep their stored names.
+    // Only names of these files have to be unique, other files may differ only in case.
+    fn get_db_files_to_update(
+        db_files: Vec<DBFile>,
+        local_files: &[(String, PathBuf)],
+        name_matching: NameMatching,
+    ) -> Result<HashMap<String, DBFile>> {
+        let local_keys: HashSet<String> = local_files
             .iter()
-            .map(|f| f.name.clone())
+            .map(|(file_name, _)| name_matching.key(file_name))
+            .collect();
+        let db_files: Vec<DBFile> = db_files
+            .into_iter()
+            .filter(|db_file| local_keys.contains(&name_matching.key(&db_file.name)))
             .collect();
 
-        for path in local_files {
-            let file_name: String = path.to_string_lossy().into();
+        if let Some(message) =
+            name_matching.duplicates_message(db_files.iter().map(|db_file| db_file.name.as_str()))
+        {
+            return Err(UpdateError::Database(format!("Cannot upload, {message}")).into());
+        }
+
+        Ok(db_files
+            .into_iter()
+            .map(|db_file| (name_matching.key(&db_file.name), db_file))
+            .collect())
+    }
+
+    async fn upload_local_files(
+        &self,
+        client: &mut DB,
+        local_files: Vec<(String, PathBuf)>,
+        db_files: &HashMap<String, DBFile>,
+    ) -> Result<UploadStats> {
+        let name_matching = self.get_name_matching()?;
+        let compression = self.get_compression()?;
+        let source = UploadSource {
+            user: Self::current_user(),
+            host: Self::current_host(),
+        };
+        let mut stats = UploadStats {
+            dry_run: self.options.dry_run,
+            ..Default::default()
+        };
+
+        for (file_name, path) in local_files {
             let metadata = fs::metadata(&path)?;
             let last_modified = metadata.modified()?;
 
             if metadata.is_file() {
-                println!(
+                progressln!(
                     "{}: Last modified {}, size {} bytes",
                     file_name,
                     Self::format_date_time(last_modified),
@@ -153,86 +739,523 @@ impl Updater<'_> {
                 );
             }
 
-            let content = fs::read(&path)?;
+            let (file_size, file_hash) = hashing::hash_reader(File::open(&path)?)?;
             let file_date: DateTime<Utc> = last_modified.into();
-            if db_files.contains(&file_name) {
-                client
-                    .update_file_content(&file_name, file_date, &content)
-                    .await?;
+            let db_file = db_files.get(&name_matching.key(&file_name));
+            match db_file {
+                Some(db_file)
+                    if db_file
+                        .hash
+                        .as_ref()
+                        .is_some_and(|db_hash| db_hash.eq_ignore_ascii_case(&file_hash)) =>
+                {
+                    progressln!("Unchanged, skipped");
+                    stats.skipped += 1;
+                    continue;
+                }
+                Some(_) if self.options.dry_run => progressln!("Would update"),
+                None if self.options.dry_run => progressln!("Would add new file"),
+                _ => {
+                    let upload = FileUpload {
+                        // existing files keep their stored names
+                        name: db_file.map_or(file_name.as_str(), |db_file| db_file.name.as_str()),
+                        date: file_date,
+                        size: file_size as i64,
+                        hash: &file_hash,
+                        compression,
+                        source: &source,
+                    };
+                    if upload.name != file_name {
+                        progressln!("Stored as {}", upload.name);
+                    }
+
+                    // the file is read again in chunks, it must not change since it was hashed
+                    let mut content = Hashing::new(File::open(&path)?);
+                    if db_file.is_some() {
+                        client.update_file_content(&upload, &mut content).await?;
+                    } else {
+                        client
+                            .insert_file_with_content(&upload, &mut content)
+                            .await?;
+                    }
+                    let (_, _, uploaded_hash) = content.finish();
+                    if uploaded_hash != file_hash {
+                        return Err(UpdateError::LocalFile(format!(
+                            "{file_name} was changed while uploading"
+                        ))
+                        .into());
+                    }
+                }
+            }
+
+            if db_file.is_some() {
+                stats.updated += 1;
             } else {
-                client
-                    .insert_file_with_content(&file_name, file_date, &content)
-                    .await?;
+                stats.inserted += 1;
             }
+            stats.bytes += file_size;
         }
 
-        Ok(())
+        Ok(stats)
     }
 
-    async fn list_files(&self, pattern_str: String) -> Result<()> {
+    // Status of each local and database file sorted by name, files only in database
+    // are reported with their stored names
+    fn get_file_statuses(
+        local_files: Vec<(String, PathBuf)>,
+        db_files: Vec<DBFile>,
+        name_matching: NameMatching,
+    ) -> Result<Vec<(String, FileStatus)>> {
+        let mut db_files: HashMap<String, DBFile> = db_files
+            .into_iter()
+            .map(|db_file| (name_matching.key(&db_file.name), db_file))
+            .collect();
+
+        let mut statuses: Vec<(String, FileStatus)> = vec![];
+
+        for (file_name, path) in local_files {
+            let db_file = db_files.remove(&name_matching.key(&file_name));
+            let local_file = Self::read_local_file(&path, db_file.as_ref())?;
+            statuses.push((
+                file_name,
+                status::classify(Some(&local_file), db_file.as_ref()),
+            ));
+        }
+
+        statuses.extend(
+            db_files
+                .into_values()
+                .map(|db_file| (db_file.name, FileStatus::OnlyInDb)),
+        );
+        statuses.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(statuses)
+    }
+
+    async fn status_files(&self, pattern_str: String) -> Result<()> {
+        let name_matching = self.get_name_matching()?;
+        let local_files =
+            Self::get_local_files(&self.get_local_dir()?, &pattern_str, name_matching)?;
+
         let mut client = self.connect().await?;
 
-        let db_files = Self::get_matched_db_files(&mut client, &pattern_str).await?;
+        let db_files =
+            Self::get_matched_db_files(&mut client, &pattern_str, name_matching).await?;
+        let statuses = Self::get_file_statuses(local_files, db_files, name_matching)?;
+
+        if self.options.format != OutputFormat::Text {
+            let records: Vec<StatusRecord> = statuses
+                .into_iter()
+                .map(|(name, status)| StatusRecord {
+                    name,
+                    status: status.to_string(),
+                })
+                .collect();
+            return output::print_records(self.options.format, &records);
+        }
+
+        progressln!();
+
+        for (file_name, status) in &statuses {
+            progressln!("{file_name}\t{status}");
+        }
+
+        progressln!();
+
+        for status in FileStatus::iter() {
+            let count = statuses.iter().filter(|(_, s)| *s == status).count();
+            if count > 0 {
+                progressln!("{count} file(s) {status}");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete_files(&self, pattern_str: String) -> Result<()> {
+        let mut client = self.connect().await?;
 
-        println!();
+        let db_files =
+            Self::get_matched_db_files(&mut client, &pattern_str, self.get_name_matching()?)
+                .await?;
+        if db_files.is_empty() {
+            progressln!("\nNo files to delete");
+            return Ok(());
+        }
 
+        progressln!("\nFiles to delete, including all stored versions:");
         for db_file in &db_files {
-            println!(
+            progressln!(
                 "{}\t{}",
                 db_file.name,
                 Self::format_db_date_time(db_file.date)
             );
         }
 
-        println!("\n{} file(s)", db_files.len());
+        if self.options.dry_run {
+            progressln!("\n{} file(s) would be deleted", db_files.len());
+            return Ok(());
+        }
+
+        if !self.options.assume_yes
+            && !Self::confirm(&format!("Delete {} file(s)?", db_files.len()))?
+        {
+            progressln!("Cancelled");
+            return Ok(());
+        }
+
+        client.begin_transaction().await?;
+        for db_file in &db_files {
+            progress!("Deleting {}...", db_file.name);
+            if let Err(error) = client.delete_file(&db_file.name).await {
+                // keep the original error, server rolls back anyway when connection closes
+                if let Err(rollback_error) = client.rollback_transaction().await {
+                    progressln!("\nRollback failed: {rollback_error}");
+                }
+                progressln!("\nDelete failed, no files were deleted");
+                return Err(error);
+            }
+            progressln!("OK");
+        }
+        client.commit_transaction().await?;
+
+        progressln!("\n{} file(s) deleted", db_files.len());
+
+        Ok(())
+    }
+
+    fn confirm(question: &str) -> Result<bool> {
+        progress!("\n{question} [y/N] ");
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+
+        let answer = answer.trim();
+        Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+    }
+
+    async fn list_files(&self, pattern_str: String) -> Result<()> {
+        let mut client = self.connect().await?;
+
+        let mut db_files =
+            Self::get_matched_db_files(&mut client, &pattern_str, self.get_name_matching()?)
+                .await?;
+        Self::sort_db_files(&mut db_files, self.options.sort);
+
+        if self.options.format != OutputFormat::Text {
+            let records: Vec<FileRecord> = db_files
+                .into_iter()
+                .map(|db_file| FileRecord {
+                    date: Self::format_iso_date_time(db_file.date),
+                    name: db_file.name,
+                    size: db_file.size,
+                    hash: db_file.hash,
+                    uploaded_by: db_file.uploaded_by,
+                    upload_host: db_file.upload_host,
+                    upload_date: db_file.upload_date.map(Self::format_iso_date_time),
+                })
+                .collect();
+            return output::print_records(self.options.format, &records);
+        }
+
+        progressln!();
+
+        for db_file in &db_files {
+            progressln!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                db_file.name,
+                Self::format_db_date_time(db_file.date),
+                db_file
+                    .size
+                    .map(Self::format_size)
+                    .unwrap_or_else(|| "-".to_owned()),
+                Self::format_uploader(
+                    db_file.uploaded_by.as_deref(),
+                    db_file.upload_host.as_deref()
+                ),
+                db_file
+                    .upload_date
+                    .map(Self::format_db_date_time)
+                    .unwrap_or_else(|| "-".to_owned()),
+                db_file.hash.as_deref().unwrap_or("-"),
+            );
+        }
+
+        let total_size: i64 = db_files.iter().filter_map(|db_file| db_file.size).sum();
+        progressln!(
+            "\n{} file(s), {} total",
+            db_files.len(),
+            Self::format_size(total_size)
+        );
 
         Ok(())
     }
 
+    // Name order is the default, the newest and the largest files come first otherwise
+    fn sort_db_files(db_files: &mut [DBFile], order: SortOrder) {
+        match order {
+            SortOrder::Name => db_files.sort_by(|a, b| a.name.cmp(&b.name)),
+            SortOrder::Date => db_files.sort_by_key(|db_file| Reverse(db_file.date)),
+            SortOrder::Size => db_files.sort_by_key(|db_file| Reverse(db_file.size)),
+        }
+    }
+
     // Choose only DB files which match any of the patterns
-    async fn get_matched_db_files(client: &mut DB, pattern_str: &str) -> Result<Vec<DBFile>> {
+    async fn get_matched_db_files(
+        client: &mut DB,
+        pattern_str: &str,
+        name_matching: NameMatching,
+    ) -> Result<Vec<DBFile>> {
         let patterns = pattern_str
             .split(';')
             .map(Pattern::new)
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| {
+                UpdateError::InvalidArguments(format!("Invalid file mask '{pattern_str}'"))
+            })?;
 
-        let db_files = client.get_db_files().await?;
+        // the same rules as for local files, "*" does not match files in subdirectories
+        let options = name_matching.match_options(true);
+
+        // the database returns candidates, LIKE does not know about separators and
+        // may ignore case, so the exact rules are applied here
+        let db_files = client
+            .get_db_files(&Self::like_patterns(pattern_str))
+            .await?;
 
         let db_files: Vec<DBFile> = db_files
             .into_iter()
             .filter(|db_file| {
                 patterns
                     .iter()
-                    .any(|pattern| pattern.matches(db_file.name.as_str()))
+                    .any(|pattern| pattern.matches_with(db_file.name.as_str(), options))
             })
             .collect();
 
+        if let Some(message) =
+            name_matching.duplicates_message(db_files.iter().map(|db_file| db_file.name.as_str()))
+        {
+            progressln!("Warning: {message}");
+        }
+
         Ok(db_files)
     }
 
-    fn get_file_mask(&self) -> Option<String> {
-        // TODO  Default mode is not implemented
-        self.config
-            .update_mode
-            .iter()
-            .find(|mode| mode.name.eq_ignore_ascii_case(&self.update_mode_name))
-            .map(|mode| mode.file_mask.clone())
-            .or_else(|| {
-                println!(
-                    "'{}' update mode not found in configuration file",
-                    self.update_mode_name
+    // LIKE patterns matching at least the same names as the file mask,
+    // empty when some pattern cannot be translated and all files have to be fetched
+    fn like_patterns(pattern_str: &str) -> Vec<String> {
+        pattern_str
+            .split(';')
+            .map(Self::like_pattern)
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default()
+    }
+
+    // "*", "**" and "**/" become "%" and "?" becomes "_", LIKE wildcards in names are
+    // escaped with brackets. Character classes are only translated when they escape
+    // a single character, e.g. "[[]".
+    fn like_pattern(pattern: &str) -> Option<String> {
+        let mut like = String::new();
+        let mut chars = pattern.chars();
+        while let Some(char) = chars.next() {
+            match char {
+                '*' => {
+                    // "**/" matches zero or more directories, so the slash is optional too
+                    if chars.as_str().starts_with("*/") {
+                        chars.nth(1);
+                    }
+                    if !like.ends_with('%') {
+                        like.push('%');
+                    }
+                }
+                '?' => like.push('_'),
+                '[' => {
+                    let class: String = chars.by_ref().take(2).collect();
+                    let mut class_chars = class.chars();
+                    match (class_chars.next(), class_chars.next()) {
+                        (Some(literal), Some(']')) if literal != '!' => {
+                            Self::push_like_literal(&mut like, literal)
+                        }
+                        _ => return None,
+                    }
+                }
+                literal => Self::push_like_literal(&mut like, literal),
+            }
+        }
+        Some(like)
+    }
+
+    fn push_like_literal(like: &mut String, literal: char) {
+        match literal {
+            '%' | '_' | '[' => {
+                like.push('[');
+                like.push(literal);
+                like.push(']');
+            }
+            _ => like.push(literal),
+        }
+    }
+
+    // Configured for the mode, names are compared ignoring case with --mask
+    fn get_name_matching(&self) -> Result<NameMatching> {
+        Ok(NameMatching {
+            case_sensitive: self
+                .get_update_mode()?
+                .is_some_and(|mode| mode.case_sensitive),
+        })
+    }
+
+    // Configured for the mode, images are not compressed with --mask
+    fn get_compression(&self) -> Result<Compression> {
+        Ok(self
+            .get_update_mode()?
+            .map_or(Compression::None, |mode| mode.compression))
+    }
+
+    // Mode given on the command line, or the default one when omitted.
+    // None when file mask is given instead of mode.
+    fn get_update_mode(&self) -> Result<Option<&UpdateMode>> {
+        if self.options.mask.is_some() {
+            return Ok(None);
+        }
+
+        let mode = match &self.update_mode_name {
+            Some(name) => self
+                .config
+                .find_mode(name)
+                .ok_or_else(|| UpdateError::ModeNotFound(name.clone()))?,
+            None => self.config.default_mode().ok_or_else(|| {
+                UpdateError::Config(
+                    "Default update mode not found in configuration file".to_owned(),
+                )
+            })?,
+        };
+        Ok(Some(mode))
+    }
+
+    // Invalid masks are reported as argument or configuration errors, depending on
+    // where the mask comes from
+    fn get_file_mask(&self) -> Result<String> {
+        let mask = match (&self.options.mask, self.get_update_mode()?) {
+            (Some(mask), _) => {
+                Self::check_mask(mask).with_context(|| {
+                    UpdateError::InvalidArguments(format!("Invalid file mask '{mask}'"))
+                })?;
+                mask
+            }
+            (None, Some(mode)) => {
+                Self::check_mask(&mode.file_mask).with_context(|| {
+                    UpdateError::Config(format!(
+                        "Invalid file mask '{}' of '{}' update mode",
+                        mode.file_mask, mode.name
+                    ))
+                })?;
+                &mode.file_mask
+            }
+            (None, None) => {
+                return Err(
+                    UpdateError::InvalidArguments("File mask is not specified".to_owned()).into(),
                 );
-                None
-            })
+            }
+        };
+        Ok(mask.clone())
+    }
+
+    fn check_mask(mask: &str) -> Result<(), glob::PatternError> {
+        mask.split(';')
+            .try_for_each(|pattern| Pattern::new(pattern).map(|_| ()))
+    }
+
+    // Directory given on the command line, then the one configured for the mode,
+    // current directory otherwise
+    fn get_local_dir(&self) -> Result<PathBuf> {
+        if let Some(dir) = &self.options.dir {
+            return Ok(dir.clone());
+        }
+
+        let local_dir = self
+            .get_update_mode()?
+            .and_then(|mode| mode.local_dir.as_deref())
+            .unwrap_or(".");
+        Ok(PathBuf::from(local_dir))
+    }
+
+    // For history and rollback commands the mode argument is the name of the file
+    fn get_file_name(&self) -> Result<&str> {
+        self.update_mode_name.as_deref().ok_or_else(|| {
+            UpdateError::InvalidArguments(format!(
+                "File name is required for {} command",
+                self.command
+            ))
+            .into()
+        })
     }
 
     async fn connect(&self) -> Result<DB> {
-        DB::connect(
-            self.config.connection_string.as_str(),
-            self.sql_username.as_deref(),
-            self.sql_password.as_deref(),
+        let mut client = DB::connect(
+            self.config
+                .connection_string(self.options.profile.as_deref())?,
+            self.options.sql_username.as_deref(),
+            self.options.sql_password.as_deref(),
+        )
+        .await?;
+
+        // init is the only command which works with an outdated schema
+        if self.command != Command::Init {
+            Self::check_schema_version(client.get_schema_version().await?)?;
+        }
+        if self.uses_file_mask() && self.get_name_matching()?.case_sensitive {
+            Self::check_file_name_collation(client.get_file_name_collation().await?)?;
+        }
+
+        Ok(client)
+    }
+
+    fn uses_file_mask(&self) -> bool {
+        matches!(
+            self.command,
+            Command::Upload | Command::Download | Command::List | Command::Status | Command::Delete
         )
-        .await
+    }
+
+    // SQL statements find files by FileName, with a collation ignoring case they would
+    // mix up files which a case sensitive mode keeps apart
+    fn check_file_name_collation(collation: Option<String>) -> Result<()> {
+        let collation = collation.unwrap_or_default();
+        if !names::is_case_sensitive_collation(&collation) {
+            return Err(UpdateError::Config(format!(
+                "Update mode is case sensitive, but collation '{collation}' of FileName column \
+                 ignores case, change it to a case sensitive one, e.g. Latin1_General_CS_AS"
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn check_schema_version(version: i32) -> Result<()> {
+        let latest_version = schema::latest_version();
+        if version < latest_version {
+            return Err(UpdateError::SchemaOutdated(format!(
+                "Database schema version {version} is older than version {latest_version} \
+                 required by this program, run 'polymix-update init' to upgrade it"
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    // Name of the OS user running the upload, recorded in version history
+    fn current_user() -> String {
+        env::var("USERNAME")
+            .or_else(|_| env::var("USER"))
+            .unwrap_or_else(|_| "unknown".to_owned())
+    }
+
+    // Name of the machine the upload is made from
+    fn current_host() -> String {
+        gethostname::gethostname().to_string_lossy().into_owned()
     }
 
     fn format_date_time(system_time: SystemTime) -> String {
@@ -243,4 +1266,366 @@ impl Updater<'_> {
     fn format_db_date_time(dt: NaiveDateTime) -> String {
         dt.format("%d/%m/%Y %T").to_string()
     }
+
+    // Dates are stored in UTC
+    fn format_local_date_time(dt: NaiveDateTime) -> String {
+        dt.and_utc()
+            .with_timezone(&Local)
+            .format("%d/%m/%Y %T")
+            .to_string()
+    }
+
+    // Size in bytes, or with one decimal in KB, MB or GB
+    fn format_size(size: i64) -> String {
+        const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+
+        if size < 1024 {
+            return format!("{size} B");
+        }
+        let mut value = size as f64 / 1024.0;
+        let mut unit = UNITS[0];
+        for next_unit in &UNITS[1..] {
+            if value < 1024.0 {
+                break;
+            }
+            value /= 1024.0;
+            unit = next_unit;
+        }
+        format!("{value:.1} {unit}")
+    }
+
+    // "user@host", or whichever of them is known
+    fn format_uploader(user: Option<&str>, host: Option<&str>) -> String {
+        match (user, host) {
+            (Some(user), Some(host)) => format!("{user}@{host}"),
+            (Some(name), None) | (None, Some(name)) => name.to_owned(),
+            (None, None) => "-".to_owned(),
+        }
+    }
+
+    // Dates are stored in UTC
+    fn format_iso_date_time(dt: NaiveDateTime) -> String {
+        dt.and_utc().to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn version(version: i32, upload_date: &str, is_current: bool) -> DBFileVersion {
+        let upload_date = NaiveDateTime::parse_from_str(upload_date, "%Y-%m-%d %T").unwrap();
+        DBFileVersion {
+            version,
+            date: upload_date,
+            hash: None,
+            uploaded_by: None,
+            upload_date: Some(upload_date),
+            is_current,
+        }
+    }
+
+    fn versions() -> Vec<DBFileVersion> {
+        vec![
+            version(1, "2026-01-10 12:00:00", false),
+            version(2, "2026-02-10 12:00:00", false),
+            version(3, "2026-03-10 12:00:00", true),
+        ]
+    }
+
+    fn db_file(name: &str, date: &str, size: Option<i64>) -> DBFile {
+        DBFile {
+            name: name.to_owned(),
+            date: NaiveDateTime::parse_from_str(date, "%Y-%m-%d %T").unwrap(),
+            size,
+            hash: None,
+            uploaded_by: None,
+            upload_host: None,
+            upload_date: None,
+        }
+    }
+
+    #[test]
+    fn select_rollback_version_defaults_to_previous() {
+        let selected = Updater::select_rollback_version(&versions(), &RollbackTarget::Previous);
+        assert_eq!(selected.unwrap(), 2);
+    }
+
+    #[test]
+    fn select_rollback_version_by_number_and_date() {
+        let selected = Updater::select_rollback_version(&versions(), &RollbackTarget::Version(1));
+        assert_eq!(selected.unwrap(), 1);
+
+        let date = NaiveDateTime::parse_from_str("2026-03-01 00:00:00", "%Y-%m-%d %T").unwrap();
+        let selected = Updater::select_rollback_version(&versions(), &RollbackTarget::Date(date));
+        assert_eq!(selected.unwrap(), 2);
+    }
+
+    #[test]
+    fn check_schema_version_requires_latest_version() {
+        assert!(Updater::check_schema_version(schema::latest_version()).is_ok());
+
+        let error = Updater::check_schema_version(0).unwrap_err();
+        assert_eq!(crate::error::exit_code(&error), crate::error::EXIT_SCHEMA_OUTDATED);
+        assert!(error.to_string().contains("polymix-update init"));
+    }
+
+    #[test]
+    fn check_file_name_collation_requires_case_sensitive_collation() {
+        let collation = Some("Latin1_General_CS_AS".to_owned());
+        assert!(Updater::check_file_name_collation(collation).is_ok());
+
+        let error =
+            Updater::check_file_name_collation(Some("SQL_Latin1_General_CP1_CI_AS".to_owned()))
+                .unwrap_err();
+        assert_eq!(crate::error::exit_code(&error), crate::error::EXIT_CONFIG);
+    }
+
+    #[test]
+    fn history_date_is_accepted_as_rollback_target() {
+        let upload_date =
+            NaiveDateTime::parse_from_str("2026-02-10 12:00:00", "%Y-%m-%d %T").unwrap();
+        let shown = Updater::format_local_date_time(upload_date);
+
+        assert_eq!(
+            RollbackTarget::from_str(&shown),
+            Ok(RollbackTarget::Date(upload_date))
+        );
+    }
+
+    #[test]
+    fn select_rollback_version_rejects_current_and_missing_versions() {
+        assert!(
+            Updater::select_rollback_version(&versions(), &RollbackTarget::Version(3)).is_err()
+        );
+        assert!(
+            Updater::select_rollback_version(&versions(), &RollbackTarget::Version(7)).is_err()
+        );
+        assert!(Updater::select_rollback_version(&[], &RollbackTarget::Previous).is_err());
+    }
+
+    #[test]
+    fn get_local_files_returns_names_relative_to_local_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("reports").join("2026")).unwrap();
+        fs::create_dir_all(dir.path().join(".polymix-update.backup")).unwrap();
+        fs::write(dir.path().join("calc.exe"), b"").unwrap();
+        fs::write(dir.path().join("prices.xls"), b"").unwrap();
+        fs::write(
+            dir.path().join("reports").join("2026").join("monthly.xls"),
+            b"",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join(".polymix-update.backup").join("old.xls"),
+            b"",
+        )
+        .unwrap();
+
+        let files = Updater::get_local_files(dir.path(), "*.EXE;**/*.xls", NameMatching::default())
+            .unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            ["calc.exe", "prices.xls", "reports/2026/monthly.xls"]
+        );
+    }
+
+    #[test]
+    fn get_local_files_in_relative_local_dir() {
+        // tests run in the package directory
+        let files =
+            Updater::get_local_files(Path::new("."), "Cargo.toml", NameMatching::default())
+                .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "Cargo.toml");
+
+        let files =
+            Updater::get_local_files(Path::new("./src"), "main.rs", NameMatching::default())
+                .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "main.rs");
+    }
+
+    #[test]
+    fn get_file_statuses_keeps_stored_names_of_files_only_in_db() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("calc.exe"), b"").unwrap();
+        let local_files = vec![("calc.exe".to_owned(), dir.path().join("calc.exe"))];
+        let db_files = vec![
+            db_file("Calc.EXE", "2026-01-10 12:00:00", Some(1)),
+            db_file("Reports/Monthly.XLS", "2026-01-10 12:00:00", Some(1)),
+        ];
+
+        let statuses =
+            Updater::get_file_statuses(local_files, db_files, NameMatching::default()).unwrap();
+        let names: Vec<&str> = statuses.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(names, ["Reports/Monthly.XLS", "calc.exe"]);
+        assert_eq!(statuses[0].1, FileStatus::OnlyInDb);
+        assert_ne!(statuses[1].1, FileStatus::OnlyInDb);
+    }
+
+    #[test]
+    fn mask_and_local_path_errors_have_exit_codes() {
+        let error =
+            Updater::get_local_files(Path::new("."), "calc[.exe", NameMatching::default())
+                .unwrap_err();
+        assert_eq!(
+            crate::error::exit_code(&error),
+            crate::error::EXIT_INVALID_ARGUMENTS
+        );
+        assert!(Updater::check_mask("*.exe;reports/**/*.xls").is_ok());
+        assert!(Updater::check_mask("*.exe;calc[.exe").is_err());
+
+        let error =
+            Updater::relative_path(Path::new("other/calc.exe"), Path::new("./dir")).unwrap_err();
+        assert_eq!(crate::error::exit_code(&error), crate::error::EXIT_LOCAL_FILE);
+    }
+
+    #[test]
+    fn get_db_files_to_update_ignores_duplicates_of_other_files() {
+        let local_files = vec![("calc.exe".to_owned(), PathBuf::from("calc.exe"))];
+        let db_files = || {
+            vec![
+                db_file("Calc.EXE", "2026-01-10 12:00:00", Some(1)),
+                db_file("sub/A.exe", "2026-01-10 12:00:00", Some(1)),
+                db_file("sub/a.exe", "2026-01-10 12:00:00", Some(1)),
+            ]
+        };
+
+        let to_update =
+            Updater::get_db_files_to_update(db_files(), &local_files, NameMatching::default())
+                .unwrap();
+        assert_eq!(to_update.len(), 1);
+        assert_eq!(to_update["calc.exe"].name, "Calc.EXE");
+
+        let local_files = vec![("sub/a.exe".to_owned(), PathBuf::from("sub/a.exe"))];
+        assert!(
+            Updater::get_db_files_to_update(db_files(), &local_files, NameMatching::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn local_file_name_uses_forward_slashes() {
+        let path: PathBuf = [".", "reports", "monthly.xls"].iter().collect();
+        assert_eq!(
+            Updater::local_file_name(&path).unwrap(),
+            "reports/monthly.xls"
+        );
+        assert_eq!(
+            Updater::local_file_name(Path::new("calc.exe")).unwrap(),
+            "calc.exe"
+        );
+
+        let path: PathBuf = ["..", "calc.exe"].iter().collect();
+        assert!(Updater::local_file_name(&path).is_err());
+    }
+
+    #[test]
+    fn safe_file_name_normalises_separators() {
+        assert_eq!(Updater::safe_file_name("calc.exe").unwrap(), "calc.exe");
+        assert_eq!(
+            Updater::safe_file_name("reports\\monthly.xls").unwrap(),
+            "reports/monthly.xls"
+        );
+        assert_eq!(
+            Updater::safe_file_name("./reports//monthly.xls").unwrap(),
+            "reports/monthly.xls"
+        );
+    }
+
+    #[test]
+    fn safe_file_name_rejects_escaping_paths() {
+        assert!(Updater::safe_file_name("..\\..\\Windows\\foo.exe").is_err());
+        assert!(Updater::safe_file_name("reports/../../foo.exe").is_err());
+        assert!(Updater::safe_file_name("/etc/passwd").is_err());
+        assert!(Updater::safe_file_name("\\\\server\\share\\foo.exe").is_err());
+        assert!(Updater::safe_file_name("C:\\Windows\\foo.exe").is_err());
+        assert!(Updater::safe_file_name("C:foo.exe").is_err());
+        assert!(Updater::safe_file_name(".polymix-update.backup/calc.exe").is_err());
+        assert!(Updater::safe_file_name("./").is_err());
+    }
+
+    #[test]
+    fn format_uploader_shows_known_parts() {
+        assert_eq!(
+            Updater::format_uploader(Some("oleksii"), Some("BUILD-PC")),
+            "oleksii@BUILD-PC"
+        );
+        assert_eq!(Updater::format_uploader(Some("oleksii"), None), "oleksii");
+        assert_eq!(Updater::format_uploader(None, None), "-");
+    }
+
+    #[test]
+    fn format_size_uses_readable_units() {
+        assert_eq!(Updater::format_size(0), "0 B");
+        assert_eq!(Updater::format_size(1023), "1023 B");
+        assert_eq!(Updater::format_size(1536), "1.5 KB");
+        assert_eq!(Updater::format_size(5 * 1024 * 1024), "5.0 MB");
+        assert_eq!(
+            Updater::format_size(3 * 1024 * 1024 * 1024 * 1024),
+            "3072.0 GB"
+        );
+    }
+
+    #[test]
+    fn sort_db_files_by_size_puts_largest_first() {
+        let mut db_files = vec![
+            db_file("a.exe", "2026-01-02 10:00:00", Some(10)),
+            db_file("b.exe", "2026-01-01 10:00:00", None),
+            db_file("c.exe", "2026-01-03 10:00:00", Some(30)),
+        ];
+
+        Updater::sort_db_files(&mut db_files, SortOrder::Size);
+        let names: Vec<&str> = db_files
+            .iter()
+            .map(|db_file| db_file.name.as_str())
+            .collect();
+        assert_eq!(names, ["c.exe", "a.exe", "b.exe"]);
+
+        Updater::sort_db_files(&mut db_files, SortOrder::Date);
+        let names: Vec<&str> = db_files
+            .iter()
+            .map(|db_file| db_file.name.as_str())
+            .collect();
+        assert_eq!(names, ["c.exe", "a.exe", "b.exe"]);
+
+        Updater::sort_db_files(&mut db_files, SortOrder::Name);
+        let names: Vec<&str> = db_files
+            .iter()
+            .map(|db_file| db_file.name.as_str())
+            .collect();
+        assert_eq!(names, ["a.exe", "b.exe", "c.exe"]);
+    }
+
+    #[test]
+    fn like_pattern_translates_wildcards_and_escapes_literals() {
+        assert_eq!(Updater::like_pattern("*.exe").unwrap(), "%.exe");
+        assert_eq!(
+            Updater::like_pattern("reports/**/*.xls").unwrap(),
+            "reports/%.xls"
+        );
+        assert_eq!(Updater::like_pattern("**/*.xls").unwrap(), "%.xls");
+        assert_eq!(
+            Updater::like_pattern("reports/**/monthly.xls").unwrap(),
+            "reports/%monthly.xls"
+        );
+        assert_eq!(Updater::like_pattern("calc?.exe").unwrap(), "calc_.exe");
+        assert_eq!(
+            Updater::like_pattern("100%_[[]v2].*").unwrap(),
+            "100[%][_][[]v2].%"
+        );
+        assert_eq!(Updater::like_pattern("[*]*").unwrap(), "*%");
+    }
+
+    #[test]
+    fn like_patterns_are_empty_when_some_pattern_is_not_translatable() {
+        assert_eq!(Updater::like_patterns("*.EXE;*.xls"), ["%.EXE", "%.xls"]);
+        assert!(Updater::like_patterns("*.exe;calc[0-9].exe").is_empty());
+        assert!(Updater::like_patterns("[!a]*").is_empty());
+    }
 }